@@ -3,7 +3,7 @@ use bevy_lit::prelude::*;
 
 fn main() {
     App::new()
-        .add_plugins((DefaultPlugins, Lighting2dPlugin))
+        .add_plugins((DefaultPlugins, Lighting2dPlugin::default()))
         .add_systems(Startup, setup)
         .add_systems(Update, update_cursor_light)
         .add_systems(FixedUpdate, update_moving_lights)
@@ -25,6 +25,7 @@ fn setup(mut commands: Commands) {
                 max_steps: 32,
                 jitter_contrib: 0.5,
                 sharpness: 10.0,
+                ..default()
             },
             ..default()
         },
@@ -53,6 +54,7 @@ fn setup(mut commands: Commands) {
             },
             LightOccluder2d {
                 half_size: Vec2::splat(50.0),
+                ..default()
             },
         ));
     });
@@ -94,6 +96,7 @@ fn setup(mut commands: Commands) {
                 radius: 400.0,
                 falloff: 3.0,
                 color: Color::srgb(1.0, 1.0, 0.0),
+                ..default()
             },
             ..default()
         },
@@ -0,0 +1,100 @@
+use bevy::{math::vec3, prelude::*};
+use bevy_lit::prelude::*;
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, Lighting2dPlugin::default()))
+        .add_systems(Startup, setup)
+        .add_systems(Update, update_moving_light)
+        .run();
+}
+
+#[derive(Component)]
+struct MovingLight;
+
+fn setup(mut commands: Commands) {
+    // Ambient occlusion darkens the ambient light around the occluders while global illumination
+    // bounces the light of the lamp off their colored walls
+    commands.spawn((
+        Camera2dBundle::default(),
+        Lighting2dSettings {
+            blur: 8.0,
+            global_illumination: Some(GlobalIllumination::default()),
+            ambient_occlusion: Some(AmbientOcclusion {
+                radius: 48.0,
+                ..default()
+            }),
+            ..default()
+        },
+        AmbientLight2d {
+            brightness: 0.1,
+            ..default()
+        },
+    ));
+
+    [
+        (vec3(-200.0, 0.0, 0.0), Color::srgb(1.0, 0.2, 0.2)),
+        (vec3(200.0, 0.0, 0.0), Color::srgb(0.2, 1.0, 0.2)),
+        (vec3(0.0, 200.0, 0.0), Color::srgb(0.2, 0.2, 1.0)),
+    ]
+    .into_iter()
+    .for_each(|(pos, albedo)| {
+        commands.spawn((
+            SpriteBundle {
+                transform: Transform::from_translation(pos),
+                sprite: Sprite {
+                    color: albedo,
+                    custom_size: Some(Vec2::splat(100.0)),
+                    ..default()
+                },
+                ..default()
+            },
+            LightOccluder2d {
+                half_size: Vec2::splat(50.0),
+                albedo,
+                ..default()
+            },
+        ));
+    });
+
+    // An emissive occluder lights its surroundings without any point light
+    commands.spawn((
+        SpriteBundle {
+            transform: Transform::from_xyz(0.0, -200.0, 0.0),
+            sprite: Sprite {
+                color: Color::srgb(1.0, 0.6, 0.2),
+                custom_size: Some(Vec2::new(200.0, 20.0)),
+                ..default()
+            },
+            ..default()
+        },
+        LightOccluder2d {
+            half_size: Vec2::new(100.0, 10.0),
+            emissive: Color::linear_rgb(2.0, 1.2, 0.4),
+            ..default()
+        },
+    ));
+
+    commands.spawn((
+        MovingLight,
+        PointLight2dBundle {
+            point_light: PointLight2d {
+                intensity: 3.0,
+                radius: 500.0,
+                falloff: 2.0,
+                ..default()
+            },
+            ..default()
+        },
+    ));
+}
+
+fn update_moving_light(
+    time: Res<Time>,
+    mut point_light_query: Query<&mut Transform, With<MovingLight>>,
+) {
+    for mut transform in &mut point_light_query {
+        let angle = time.elapsed_seconds() / 2.0;
+        transform.translation = Vec3::new(angle.cos(), angle.sin(), 0.0) * 100.0;
+    }
+}
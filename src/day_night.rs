@@ -0,0 +1,294 @@
+use bevy::prelude::*;
+
+use crate::prelude::AmbientLight2d;
+
+/// Number of hours in a day of a [`DayNightCycle2d`].
+pub const HOURS_PER_DAY: f32 = 24.0;
+
+/// A value that can be keyframed in a [`DayNightGradient`].
+pub trait DayNightValue: Clone {
+    /// Interpolates between `self` at `0.0` and `other` at `1.0`.
+    fn interpolate(&self, other: &Self, t: f32) -> Self;
+}
+
+impl DayNightValue for f32 {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        self.lerp(*other, t)
+    }
+}
+
+impl DayNightValue for Color {
+    fn interpolate(&self, other: &Self, t: f32) -> Self {
+        // Mixed in linear space so the interpolated brightness stays physically plausible
+        self.to_linear().mix(&other.to_linear(), t).into()
+    }
+}
+
+/// Values keyframed over the hours of a day. Values between keyframes are interpolated linearly,
+/// and the last keyframe wraps around midnight to the first one.
+#[derive(Clone, Debug, Reflect, PartialEq)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(from = "Vec<(f32, T)>", into = "Vec<(f32, T)>")
+)]
+pub struct DayNightGradient<T: DayNightValue> {
+    keyframes: Vec<(f32, T)>,
+}
+
+impl<T: DayNightValue> DayNightGradient<T> {
+    /// Creates a gradient from `(hour, value)` keyframes, in any order. Hours are wrapped to
+    /// `[0, 24)`.
+    pub fn new(keyframes: impl IntoIterator<Item = (f32, T)>) -> Self {
+        let mut keyframes = keyframes
+            .into_iter()
+            .map(|(hour, value)| (hour.rem_euclid(HOURS_PER_DAY), value))
+            .collect::<Vec<_>>();
+
+        keyframes.sort_by(|(a, _), (b, _)| a.total_cmp(b));
+
+        Self { keyframes }
+    }
+
+    /// A gradient holding the same value all day.
+    pub fn constant(value: T) -> Self {
+        Self::new([(0.0, value)])
+    }
+
+    /// The keyframes sorted by hour.
+    pub fn keyframes(&self) -> &[(f32, T)] {
+        &self.keyframes
+    }
+
+    /// Evaluates the gradient at `hour`, or returns `None` if it has no keyframes.
+    pub fn sample(&self, hour: f32) -> Option<T> {
+        let hour = hour.rem_euclid(HOURS_PER_DAY);
+
+        // The keyframes surrounding the hour, wrapping around midnight
+        let next_index = self
+            .keyframes
+            .iter()
+            .position(|(key_hour, _)| *key_hour > hour)
+            .unwrap_or(0);
+        let previous_index = next_index
+            .checked_sub(1)
+            .unwrap_or(self.keyframes.len().checked_sub(1)?);
+
+        let (previous_hour, previous) = &self.keyframes[previous_index];
+        let (next_hour, next) = &self.keyframes[next_index];
+
+        let span = (next_hour - previous_hour).rem_euclid(HOURS_PER_DAY);
+        if span <= 0.0 {
+            return Some(previous.clone());
+        }
+
+        let t = (hour - previous_hour).rem_euclid(HOURS_PER_DAY) / span;
+        Some(previous.interpolate(next, t))
+    }
+}
+
+impl<T: DayNightValue> From<Vec<(f32, T)>> for DayNightGradient<T> {
+    fn from(keyframes: Vec<(f32, T)>) -> Self {
+        Self::new(keyframes)
+    }
+}
+
+impl<T: DayNightValue> From<DayNightGradient<T>> for Vec<(f32, T)> {
+    fn from(gradient: DayNightGradient<T>) -> Self {
+        gradient.keyframes
+    }
+}
+
+/// Drives the [`AmbientLight2d`] of a [`Camera2d`] entity over a day. The clock advances every
+/// frame and the ambient light is overwritten with the value of the gradients at the current
+/// time of day, the component being inserted if missing. A
+/// [`LightingProfile2d`](crate::prelude::LightingProfile2d) on the same camera leaves the ambient
+/// light to the cycle.
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
+pub struct DayNightCycle2d {
+    /// The current time of day, in hours in `[0, 24)`.
+    pub time_of_day: f32,
+    /// In-game hours per real second. Zero pauses the cycle, negative values rewind it.
+    pub speed: f32,
+    /// The color of the ambient light over the day.
+    pub ambient_color: DayNightGradient<Color>,
+    /// The brightness of the ambient light over the day.
+    pub ambient_brightness: DayNightGradient<f32>,
+}
+
+impl Default for DayNightCycle2d {
+    fn default() -> Self {
+        Self {
+            time_of_day: 12.0,
+            speed: 0.1,
+            ambient_color: DayNightGradient::new([
+                (0.0, Color::srgb(0.25, 0.3, 0.6)),
+                (6.0, Color::srgb(1.0, 0.6, 0.4)),
+                (9.0, Color::WHITE),
+                (17.0, Color::WHITE),
+                (19.0, Color::srgb(1.0, 0.5, 0.3)),
+                (21.0, Color::srgb(0.25, 0.3, 0.6)),
+            ]),
+            ambient_brightness: DayNightGradient::new([
+                (0.0, 0.15),
+                (5.0, 0.15),
+                (8.0, 1.0),
+                (18.0, 1.0),
+                (21.0, 0.15),
+            ]),
+        }
+    }
+}
+
+impl DayNightCycle2d {
+    /// The ambient light at the current time of day.
+    pub fn ambient_light(&self) -> AmbientLight2d {
+        let default = AmbientLight2d::default();
+
+        AmbientLight2d {
+            color: self
+                .ambient_color
+                .sample(self.time_of_day)
+                .unwrap_or(default.color),
+            brightness: self
+                .ambient_brightness
+                .sample(self.time_of_day)
+                .unwrap_or(default.brightness),
+        }
+    }
+}
+
+pub fn update_day_night_cycles(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut cycles_query: Query<(Entity, &mut DayNightCycle2d, Option<&mut AmbientLight2d>)>,
+) {
+    for (entity, mut cycle, ambient_light) in &mut cycles_query {
+        if cycle.speed != 0.0 {
+            cycle.time_of_day =
+                (cycle.time_of_day + cycle.speed * time.delta_seconds()).rem_euclid(HOURS_PER_DAY);
+        }
+
+        let value = cycle.ambient_light();
+
+        match ambient_light {
+            Some(mut ambient_light) => *ambient_light = value,
+            None => {
+                commands.entity(entity).insert(value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    #[test]
+    fn empty_gradient_has_no_value() {
+        let gradient = DayNightGradient::<f32>::new([]);
+
+        assert_eq!(gradient.sample(12.0), None);
+    }
+
+    #[test]
+    fn single_keyframe_is_constant() {
+        let gradient = DayNightGradient::constant(0.5);
+
+        for hour in [0.0, 6.0, 12.0, 23.9] {
+            assert_eq!(gradient.sample(hour), Some(0.5));
+        }
+    }
+
+    #[test]
+    fn samples_keyframes_exactly() {
+        let gradient = DayNightGradient::new([(6.0, 0.2), (18.0, 1.0)]);
+
+        assert_eq!(gradient.sample(6.0), Some(0.2));
+        assert_eq!(gradient.sample(18.0), Some(1.0));
+    }
+
+    #[test]
+    fn interpolates_between_keyframes() {
+        let gradient = DayNightGradient::new([(6.0, 0.0), (18.0, 1.0)]);
+
+        assert_eq!(gradient.sample(12.0), Some(0.5));
+        assert_eq!(gradient.sample(9.0), Some(0.25));
+    }
+
+    #[test]
+    fn wraps_around_midnight() {
+        let gradient = DayNightGradient::new([(6.0, 1.0), (22.0, 0.0)]);
+
+        // 8 hours from 22:00 to 6:00, midnight is a quarter of the way
+        assert_eq!(gradient.sample(0.0), Some(0.25));
+        assert_eq!(gradient.sample(2.0), Some(0.5));
+        assert_eq!(gradient.sample(23.0), Some(0.125));
+    }
+
+    #[test]
+    fn wraps_hours_out_of_range() {
+        let gradient = DayNightGradient::new([(6.0, 0.0), (18.0, 1.0)]);
+
+        assert_eq!(gradient.sample(36.0), gradient.sample(12.0));
+        assert_eq!(gradient.sample(-12.0), gradient.sample(12.0));
+    }
+
+    #[test]
+    fn sorts_keyframes() {
+        let gradient = DayNightGradient::new([(18.0, 1.0), (30.0, 0.0)]);
+
+        assert_eq!(gradient.keyframes(), &[(6.0, 0.0), (18.0, 1.0)]);
+        assert_eq!(gradient.sample(12.0), Some(0.5));
+    }
+
+    #[test]
+    fn interpolates_colors_in_linear_space() {
+        let gradient = DayNightGradient::new([
+            (0.0, Color::LinearRgba(LinearRgba::BLACK)),
+            (12.0, Color::LinearRgba(LinearRgba::WHITE)),
+        ]);
+
+        let color = gradient.sample(6.0).unwrap().to_linear();
+
+        assert!((color.red - 0.5).abs() < 1e-6);
+        assert!((color.green - 0.5).abs() < 1e-6);
+        assert!((color.blue - 0.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn cycle_drives_ambient_light() {
+        let mut world = World::new();
+
+        let mut time = Time::<()>::default();
+        time.advance_by(Duration::from_secs(2));
+        world.insert_resource(time);
+
+        let entity = world
+            .spawn(DayNightCycle2d {
+                time_of_day: 23.0,
+                speed: 1.0,
+                ambient_color: DayNightGradient::constant(Color::WHITE),
+                ambient_brightness: DayNightGradient::new([(0.0, 0.0), (12.0, 1.0)]),
+            })
+            .id();
+
+        world.run_system_once(update_day_night_cycles);
+
+        let cycle = world.get::<DayNightCycle2d>(entity).unwrap();
+        assert_eq!(cycle.time_of_day, 1.0);
+
+        let ambient_light = world.get::<AmbientLight2d>(entity).unwrap();
+        assert_eq!(ambient_light.brightness, 1.0 / 12.0);
+    }
+}
@@ -1,20 +1,116 @@
+use std::ops::Range;
+
 use bevy::{
+    core::FrameCount,
+    ecs::entity::EntityHashMap,
     prelude::*,
-    render::{render_resource::ShaderType, view::ViewVisibility, Extract},
+    render::{view::ViewVisibility, Extract},
+};
+
+use crate::{prelude::*, prepare::LightingArrayItem};
+
+pub use shader_types::{
+    ExtractedFogVolume2d, ExtractedLightOccluder2d, ExtractedLighting2dSettings,
+    ExtractedPointLight2d, ExtractedRaymarchSettings, ExtractedTemporalAccumulation,
 };
 
-use crate::prelude::*;
+// The `ShaderType` derive checks the field types in closures that recent compilers report as dead
+// code. Attributes on the structs don't reach them, so the allow is scoped to this module.
+#[allow(dead_code)]
+mod shader_types {
+    use bevy::{prelude::*, render::render_resource::ShaderType};
+
+    #[derive(Component, Clone, ShaderType)]
+    pub struct ExtractedLighting2dSettings {
+        pub blur: f32,
+        pub fixed_resolution: u32,
+        pub point_light_count: u32,
+        pub occluder_count: u32,
+        pub ambient_light: LinearRgba,
+        pub raymarch: ExtractedRaymarchSettings,
+        pub frame: u32,
+        pub scattering: LinearRgba,
+        pub fog_volume_count: u32,
+        /// Seconds since startup, scrolls the fog noise
+        pub time: f32,
+        pub ambient_occlusion_radius: f32,
+        pub ambient_occlusion_intensity: f32,
+        /// 0 when ambient occlusion is disabled
+        pub ambient_occlusion_samples: u32,
+    }
 
-#[derive(Component, Clone, ShaderType)]
-pub struct ExtractedLighting2dSettings {
-    pub blur: f32,
-    pub fixed_resolution: u32,
-    pub ambient_light: LinearRgba,
-    pub raymarch: RaymarchSettings,
+    #[derive(Clone, ShaderType)]
+    pub struct ExtractedRaymarchSettings {
+        pub max_steps: u32,
+        pub jitter_contrib: f32,
+        pub sharpness: f32,
+        pub jitter_source: u32,
+    }
+
+    #[derive(Component, Clone, ShaderType)]
+    pub struct ExtractedTemporalAccumulation {
+        pub previous_clip_from_world: Mat4,
+        pub blend_factor: f32,
+        /// Set when there's no usable history, e.g. on the first frame or after a resize
+        pub reset: u32,
+    }
+
+    #[derive(Clone, ShaderType)]
+    pub struct ExtractedLightOccluder2d {
+        pub center: Vec2,
+        pub half_size: Vec2,
+        pub albedo: LinearRgba,
+        pub emissive: LinearRgba,
+    }
+
+    #[derive(Clone, ShaderType)]
+    pub struct ExtractedFogVolume2d {
+        pub center: Vec2,
+        /// Half size of a rect, or the radius of a circle in both components
+        pub half_size: Vec2,
+        pub noise_scroll: Vec2,
+        pub color: LinearRgba,
+        pub density: f32,
+        pub circle: u32,
+        pub noise: u32,
+    }
+
+    #[derive(Clone, ShaderType)]
+    pub struct ExtractedPointLight2d {
+        pub center: Vec2,
+        pub color: LinearRgba,
+        pub falloff: f32,
+        pub intensity: f32,
+        pub radius: f32,
+        pub cast_shadows: u32,
+        pub source_radius: f32,
+        pub scattering: f32,
+    }
+}
+
+impl From<&RaymarchSettings> for ExtractedRaymarchSettings {
+    fn from(settings: &RaymarchSettings) -> Self {
+        Self {
+            max_steps: settings.max_steps,
+            jitter_contrib: settings.jitter_contrib,
+            sharpness: settings.sharpness,
+            jitter_source: match settings.jitter_source {
+                JitterSource::Hash => 0,
+                JitterSource::BlueNoise => 1,
+                JitterSource::InterleavedGradient => 2,
+            },
+        }
+    }
 }
 
 pub fn extract_lighting_settings(
     mut commands: Commands,
+    point_lights: Res<ExtractedArray<ExtractedPointLight2d>>,
+    light_budgets: Res<ExtractedLightBudgets>,
+    light_occluders: Res<ExtractedArray<ExtractedLightOccluder2d>>,
+    fog_volumes: Res<ExtractedArray<ExtractedFogVolume2d>>,
+    frame_count: Extract<Res<FrameCount>>,
+    time: Extract<Res<Time>>,
     ambient_light_query: Extract<
         Query<(Entity, &Lighting2dSettings, Option<&AmbientLight2d>), With<Camera2d>>,
     >,
@@ -26,15 +122,40 @@ pub fn extract_lighting_settings(
                 color: Color::WHITE,
                 brightness: 1.0,
             });
+            let ambient_occlusion =
+                settings
+                    .ambient_occlusion
+                    .clone()
+                    .unwrap_or(AmbientOcclusion {
+                        radius: 0.0,
+                        intensity: 0.0,
+                        samples: 0,
+                    });
 
             (
                 e,
-                ExtractedLighting2dSettings {
-                    blur: settings.blur,
-                    fixed_resolution: if settings.fixed_resolution { 1 } else { 0 },
-                    ambient_light: ambient_light.color.to_linear() * ambient_light.brightness,
-                    raymarch: settings.raymarch.clone(),
-                },
+                (
+                    settings.clone(),
+                    ExtractedLighting2dSettings {
+                        blur: settings.blur,
+                        fixed_resolution: if settings.fixed_resolution { 1 } else { 0 },
+                        point_light_count: light_budgets
+                            .get(&e)
+                            .map_or(point_lights.len(), Vec::len)
+                            as u32,
+                        occluder_count: light_occluders.len() as u32,
+                        ambient_light: ambient_light.color.to_linear() * ambient_light.brightness,
+                        raymarch: (&settings.raymarch).into(),
+                        frame: frame_count.0,
+                        scattering: settings.scattering.color.to_linear()
+                            * settings.scattering.intensity,
+                        fog_volume_count: fog_volumes.len() as u32,
+                        time: time.elapsed_seconds_wrapped(),
+                        ambient_occlusion_radius: ambient_occlusion.radius.max(0.0),
+                        ambient_occlusion_intensity: ambient_occlusion.intensity,
+                        ambient_occlusion_samples: ambient_occlusion.samples,
+                    },
+                ),
             )
         })
         .collect::<Vec<_>>();
@@ -42,65 +163,309 @@ pub fn extract_lighting_settings(
     commands.insert_or_spawn_batch(values);
 }
 
-#[derive(Component, Default, Clone, ShaderType)]
-pub struct ExtractedLightOccluder2d {
-    pub center: Vec2,
-    pub half_size: Vec2,
+pub fn extract_lighting_debug(
+    mut commands: Commands,
+    views_query: Extract<Query<(Entity, &Lighting2dDebug), With<Lighting2dSettings>>>,
+) {
+    let values = views_query
+        .iter()
+        .map(|(entity, debug)| (entity, *debug))
+        .collect::<Vec<_>>();
+
+    commands.insert_or_spawn_batch(values);
 }
 
-pub fn extract_light_occluders(
+pub fn extract_temporal_accumulation(
     mut commands: Commands,
-    mut previous_len: Local<usize>,
-    light_occluders_query: Extract<
-        Query<(Entity, &LightOccluder2d, &GlobalTransform, &ViewVisibility)>,
+    mut previous_views: Local<EntityHashMap<(Mat4, UVec2)>>,
+    views_query: Extract<
+        Query<(Entity, &Camera, &GlobalTransform, &Lighting2dSettings), With<Camera2d>>,
     >,
 ) {
-    commands.spawn(ExtractedLightOccluder2d::default());
+    let mut next_views = EntityHashMap::default();
+    let mut values = Vec::new();
+
+    for (entity, camera, transform, settings) in &views_query {
+        let (Some(temporal_accumulation), Some(size), true) = (
+            &settings.temporal_accumulation,
+            camera.physical_viewport_size(),
+            camera.is_active,
+        ) else {
+            continue;
+        };
 
-    let mut values = Vec::with_capacity(*previous_len);
+        let clip_from_world = camera.clip_from_view() * transform.compute_matrix().inverse();
+
+        let (previous_clip_from_world, reset) = match previous_views.get(&entity) {
+            Some(&(previous_clip_from_world, previous_size)) if previous_size == size => {
+                (previous_clip_from_world, false)
+            }
+            _ => (clip_from_world, true),
+        };
+
+        next_views.insert(entity, (clip_from_world, size));
+        values.push((
+            entity,
+            ExtractedTemporalAccumulation {
+                previous_clip_from_world,
+                blend_factor: temporal_accumulation.blend_factor.clamp(0.0, 1.0),
+                reset: reset as u32,
+            },
+        ));
+    }
+
+    *previous_views = next_views;
+    commands.insert_or_spawn_batch(values);
+}
+
+/// A densely packed mirror of a main world component set that persists across frames.
+///
+/// Render world entities are cleared every frame, so extracted lights and occluders live in this
+/// resource instead. Each main world entity owns a slot, and the range of slots written since the
+/// last upload is tracked so only that part of the GPU buffer has to be rewritten.
+#[derive(Resource)]
+pub struct ExtractedArray<T> {
+    values: Vec<T>,
+    entities: Vec<Entity>,
+    indices: EntityHashMap<usize>,
+    dirty: Option<Range<usize>>,
+}
+
+impl<T> Default for ExtractedArray<T> {
+    fn default() -> Self {
+        Self {
+            values: Vec::new(),
+            entities: Vec::new(),
+            indices: EntityHashMap::default(),
+            dirty: None,
+        }
+    }
+}
+
+impl<T> ExtractedArray<T> {
+    pub fn values(&self) -> &[T] {
+        &self.values
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn contains(&self, entity: Entity) -> bool {
+        self.indices.contains_key(&entity)
+    }
+
+    /// Inserts or replaces the value extracted from `entity`.
+    pub fn insert(&mut self, entity: Entity, value: T) {
+        let index = match self.indices.get(&entity) {
+            Some(&index) => {
+                self.values[index] = value;
+                index
+            }
+            None => {
+                let index = self.values.len();
+                self.indices.insert(entity, index);
+                self.entities.push(entity);
+                self.values.push(value);
+                index
+            }
+        };
+
+        self.mark_dirty(index);
+    }
+
+    /// Removes the value extracted from `entity`, moving the last value into its slot.
+    pub fn remove(&mut self, entity: Entity) {
+        let Some(index) = self.indices.remove(&entity) else {
+            return;
+        };
+
+        self.values.swap_remove(index);
+        self.entities.swap_remove(index);
+
+        if let Some(&moved) = self.entities.get(index) {
+            self.indices.insert(moved, index);
+            self.mark_dirty(index);
+        }
+
+        // The vacated last slot
+        self.mark_dirty(self.values.len());
+    }
+
+    /// Returns the range of slots written since the last call. It may extend past the current
+    /// length to cover slots vacated by removals.
+    pub fn take_dirty(&mut self) -> Option<Range<usize>> {
+        self.dirty.take()
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1,
+        });
+    }
+}
+
+impl LightingArrayItem for ExtractedLightOccluder2d {
+    fn padding() -> Self {
+        // As far away as possible, so it never becomes the closest occluder
+        Self {
+            center: Vec2::splat(f32::MAX),
+            half_size: Vec2::ZERO,
+            albedo: LinearRgba::NONE,
+            emissive: LinearRgba::NONE,
+        }
+    }
+}
+
+pub fn extract_light_occluders(
+    mut light_occluders: ResMut<ExtractedArray<ExtractedLightOccluder2d>>,
+    mut removed_light_occluders: Extract<RemovedComponents<LightOccluder2d>>,
+    light_occluders_query: Extract<
+        Query<(
+            Entity,
+            Ref<LightOccluder2d>,
+            Ref<GlobalTransform>,
+            &ViewVisibility,
+        )>,
+    >,
+) {
+    for entity in removed_light_occluders.read() {
+        light_occluders.remove(entity);
+    }
 
     for (entity, light_occluder, transform, view_visibility) in &light_occluders_query {
         if !view_visibility.get() {
+            light_occluders.remove(entity);
             continue;
         }
 
-        values.push((
+        if light_occluders.contains(entity)
+            && !light_occluder.is_changed()
+            && !transform.is_changed()
+        {
+            continue;
+        }
+
+        light_occluders.insert(
             entity,
             ExtractedLightOccluder2d {
                 half_size: light_occluder.half_size,
                 center: transform.translation().xy(),
+                albedo: light_occluder.albedo.to_linear(),
+                emissive: light_occluder.emissive.to_linear(),
             },
-        ));
+        );
     }
+}
 
-    *previous_len = values.len();
-    commands.insert_or_spawn_batch(values);
+impl LightingArrayItem for ExtractedFogVolume2d {
+    // Fog attenuates the light of every batch, so the lighting passes can't split it
+    const BATCHED: bool = false;
+
+    fn padding() -> Self {
+        // Without density, it doesn't absorb nor scatter any light
+        Self {
+            center: Vec2::ZERO,
+            half_size: Vec2::ZERO,
+            noise_scroll: Vec2::ZERO,
+            color: LinearRgba::NONE,
+            density: 0.0,
+            circle: 0,
+            noise: 0,
+        }
+    }
+}
+
+pub fn extract_fog_volumes(
+    mut fog_volumes: ResMut<ExtractedArray<ExtractedFogVolume2d>>,
+    mut removed_fog_volumes: Extract<RemovedComponents<FogVolume2d>>,
+    fog_volumes_query: Extract<
+        Query<(
+            Entity,
+            Ref<FogVolume2d>,
+            Ref<GlobalTransform>,
+            &ViewVisibility,
+        )>,
+    >,
+) {
+    for entity in removed_fog_volumes.read() {
+        fog_volumes.remove(entity);
+    }
+
+    for (entity, fog_volume, transform, view_visibility) in &fog_volumes_query {
+        if !view_visibility.get() {
+            fog_volumes.remove(entity);
+            continue;
+        }
+
+        if fog_volumes.contains(entity) && !fog_volume.is_changed() && !transform.is_changed() {
+            continue;
+        }
+
+        let (half_size, circle) = match fog_volume.shape {
+            FogShape2d::Rect { half_size } => (half_size, false),
+            FogShape2d::Circle { radius } => (Vec2::splat(radius), true),
+        };
+
+        fog_volumes.insert(
+            entity,
+            ExtractedFogVolume2d {
+                center: transform.translation().xy(),
+                half_size,
+                noise_scroll: fog_volume.noise_scroll.unwrap_or_default(),
+                color: fog_volume.color.to_linear(),
+                density: fog_volume.density.max(0.0),
+                circle: circle as u32,
+                noise: fog_volume.noise_scroll.is_some() as u32,
+            },
+        );
+    }
 }
 
-#[derive(Component, Default, Clone, ShaderType)]
-pub struct ExtractedPointLight2d {
-    pub center: Vec2,
-    pub color: LinearRgba,
-    pub falloff: f32,
-    pub intensity: f32,
-    pub radius: f32,
+impl LightingArrayItem for ExtractedPointLight2d {
+    fn padding() -> Self {
+        // A light without radius reaches no fragment
+        Self {
+            center: Vec2::ZERO,
+            color: LinearRgba::NONE,
+            falloff: 0.0,
+            intensity: 0.0,
+            radius: 0.0,
+            cast_shadows: 0,
+            source_radius: 0.0,
+            scattering: 0.0,
+        }
+    }
 }
 
 pub fn extract_point_lights(
-    mut commands: Commands,
-    mut previous_len: Local<usize>,
-    point_lights_query: Extract<Query<(Entity, &PointLight2d, &GlobalTransform, &ViewVisibility)>>,
+    mut point_lights: ResMut<ExtractedArray<ExtractedPointLight2d>>,
+    mut removed_point_lights: Extract<RemovedComponents<PointLight2d>>,
+    point_lights_query: Extract<
+        Query<(
+            Entity,
+            Ref<PointLight2d>,
+            Ref<GlobalTransform>,
+            &ViewVisibility,
+        )>,
+    >,
 ) {
-    commands.spawn(ExtractedPointLight2d::default());
-
-    let mut values = Vec::with_capacity(*previous_len);
+    for entity in removed_point_lights.read() {
+        point_lights.remove(entity);
+    }
 
-    for (entity, point_light, transform, visibility) in point_lights_query.iter() {
+    for (entity, point_light, transform, visibility) in &point_lights_query {
         if !visibility.get() {
+            point_lights.remove(entity);
             continue;
         }
 
-        values.push((
+        if point_lights.contains(entity) && !point_light.is_changed() && !transform.is_changed() {
+            continue;
+        }
+
+        point_lights.insert(
             entity,
             ExtractedPointLight2d {
                 color: point_light.color.to_linear(),
@@ -108,10 +473,210 @@ pub fn extract_point_lights(
                 radius: point_light.radius,
                 intensity: point_light.intensity,
                 falloff: point_light.falloff,
+                cast_shadows: 1,
+                source_radius: point_light.source_radius,
+                scattering: point_light.scattering,
             },
-        ));
+        );
     }
+}
 
-    *previous_len = values.len();
-    commands.insert_or_spawn_batch(values);
+/// Point lights selected for each view with a [`LightBudget`], keyed by view entity. Views without
+/// a budget use every light in [`ExtractedArray<ExtractedPointLight2d>`].
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ExtractedLightBudgets(EntityHashMap<Vec<ExtractedPointLight2d>>);
+
+#[derive(Clone, Copy)]
+pub struct LightFade {
+    value: f32,
+    cast_shadows: bool,
+}
+
+/// Lights in the budget of a view, best first, and what they were ranked against
+pub struct LightRanking {
+    view_center: Vec2,
+    view_area: Rect,
+    slots: u32,
+    lights: Vec<Entity>,
+}
+
+pub fn extract_light_budgets(
+    mut light_budgets: ResMut<ExtractedLightBudgets>,
+    mut fades: Local<EntityHashMap<EntityHashMap<LightFade>>>,
+    mut rankings: Local<EntityHashMap<LightRanking>>,
+    mut visible_lights: Local<usize>,
+    time: Extract<Res<Time>>,
+    views_query: Extract<
+        Query<
+            (
+                Entity,
+                &Lighting2dSettings,
+                &GlobalTransform,
+                &OrthographicProjection,
+            ),
+            With<Camera2d>,
+        >,
+    >,
+    mut removed_point_lights: Extract<RemovedComponents<PointLight2d>>,
+    point_lights_query: Extract<
+        Query<(
+            Entity,
+            Ref<PointLight2d>,
+            Ref<GlobalTransform>,
+            Ref<InheritedVisibility>,
+            &ViewVisibility,
+        )>,
+    >,
+) {
+    light_budgets.clear();
+
+    // `ViewVisibility` is reset every frame, so lights culled or uncovered without moving are
+    // caught by the number of visible lights instead
+    let mut lights_changed = removed_point_lights.read().count() > 0;
+    let mut visible = 0;
+    for (_, point_light, transform, inherited_visibility, visibility) in &point_lights_query {
+        lights_changed |=
+            point_light.is_changed() || transform.is_changed() || inherited_visibility.is_changed();
+        visible += visibility.get() as usize;
+    }
+    lights_changed |= std::mem::replace(&mut *visible_lights, visible) != visible;
+
+    for (view, settings, view_transform, projection) in &views_query {
+        let budget = &settings.light_budget;
+
+        let Some(max_lights) = budget.max_lights else {
+            continue;
+        };
+
+        let view_center = view_transform.translation().xy();
+        let view_area = Rect::from_center_half_size(
+            view_center + projection.area.center(),
+            projection.area.half_size(),
+        );
+        let slots = max_lights.saturating_add(budget.unshadowed_lights);
+
+        // Ranked again only when the lights, the view or the budget changed
+        let ranked = !lights_changed
+            && rankings.get(&view).is_some_and(|ranking| {
+                ranking.view_center == view_center
+                    && ranking.view_area == view_area
+                    && ranking.slots == slots
+            });
+
+        if !ranked {
+            let mut candidates = point_lights_query
+                .iter()
+                .filter(|(.., visibility)| visibility.get())
+                .filter_map(|(entity, point_light, transform, ..)| {
+                    let center = transform.translation().xy();
+                    let light_area =
+                        Rect::from_center_half_size(center, Vec2::splat(point_light.radius));
+                    let coverage = light_area.intersect(view_area).size().element_product()
+                        / view_area.size().element_product();
+
+                    (coverage > 0.0).then_some((
+                        entity,
+                        point_light.priority,
+                        coverage,
+                        center.distance_squared(view_center),
+                    ))
+                })
+                .collect::<Vec<_>>();
+
+            candidates.sort_unstable_by(
+                |(_, a_priority, a_coverage, a_distance),
+                 (_, b_priority, b_coverage, b_distance)| {
+                    b_priority
+                        .cmp(a_priority)
+                        .then(b_coverage.total_cmp(a_coverage))
+                        .then(a_distance.total_cmp(b_distance))
+                },
+            );
+
+            rankings.insert(
+                view,
+                LightRanking {
+                    view_center,
+                    view_area,
+                    slots,
+                    lights: candidates
+                        .into_iter()
+                        .take(slots as usize)
+                        .map(|(entity, ..)| entity)
+                        .collect(),
+                },
+            );
+        }
+
+        // Lights in budget on the first frame of a view are shown right away, without fading in
+        let first_frame = !fades.contains_key(&view);
+        let view_fades = fades.entry(view).or_default();
+        let fade_step = if budget.fade_duration > 0.0 && !first_frame {
+            time.delta_seconds() / budget.fade_duration
+        } else {
+            1.0
+        };
+
+        let mut lights = Vec::new();
+        let mut next_fades = EntityHashMap::default();
+        let mut push_light = |entity, point_light: &PointLight2d, center, fade: LightFade| {
+            if fade.value <= 0.0 {
+                return;
+            }
+
+            next_fades.insert(entity, fade);
+            lights.push(ExtractedPointLight2d {
+                color: point_light.color.to_linear(),
+                center,
+                radius: point_light.radius,
+                intensity: point_light.intensity * fade.value,
+                falloff: point_light.falloff,
+                cast_shadows: fade.cast_shadows as u32,
+                source_radius: point_light.source_radius,
+                scattering: point_light.scattering,
+            });
+        };
+
+        for (rank, &entity) in rankings[&view].lights.iter().enumerate() {
+            let Ok((_, point_light, transform, ..)) = point_lights_query.get(entity) else {
+                continue;
+            };
+
+            let value = view_fades.remove(&entity).map_or(0.0, |fade| fade.value);
+
+            push_light(
+                entity,
+                &point_light,
+                transform.translation().xy(),
+                LightFade {
+                    value: (value + fade_step).min(1.0),
+                    cast_shadows: (rank as u32) < max_lights,
+                },
+            );
+        }
+
+        // Lights that left the budget, the view or stopped being visible fade out, keeping the
+        // shadows they had
+        for (entity, fade) in view_fades.drain() {
+            let Ok((_, point_light, transform, ..)) = point_lights_query.get(entity) else {
+                continue;
+            };
+
+            push_light(
+                entity,
+                &point_light,
+                transform.translation().xy(),
+                LightFade {
+                    value: (fade.value - fade_step).max(0.0),
+                    cast_shadows: fade.cast_shadows,
+                },
+            );
+        }
+
+        *view_fades = next_fades;
+        light_budgets.insert(view, lights);
+    }
+
+    fades.retain(|view, _| light_budgets.contains_key(view));
+    rankings.retain(|view, _| light_budgets.contains_key(view));
 }
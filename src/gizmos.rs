@@ -0,0 +1,135 @@
+use bevy::{prelude::*, transform::TransformSystem};
+
+use crate::prelude::{FogShape2d, FogVolume2d, LightOccluder2d, PointLight2d};
+
+/// An opt-in plugin drawing the lighting components with gizmos, so invisible occluders and the
+/// reach of each light can be seen while designing a level. Requires the `gizmos` feature.
+///
+/// What gets drawn is configured with the [`Lighting2dGizmoConfigGroup`] of the
+/// [`GizmoConfigStore`].
+#[derive(Default)]
+pub struct Lighting2dGizmosPlugin;
+
+impl Plugin for Lighting2dGizmosPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<Lighting2dGizmoConfigGroup>()
+            .init_gizmo_group::<Lighting2dGizmoConfigGroup>()
+            .add_systems(
+                PostUpdate,
+                (
+                    draw_point_light_gizmos,
+                    draw_light_occluder_gizmos,
+                    draw_fog_volume_gizmos,
+                )
+                    .after(TransformSystem::TransformPropagate),
+            );
+    }
+}
+
+/// Gizmo categories and colors of the [`Lighting2dGizmosPlugin`].
+#[derive(Clone, Reflect, GizmoConfigGroup)]
+pub struct Lighting2dGizmoConfigGroup {
+    /// Draws the radius of every [`PointLight2d`], and its source radius if any
+    pub point_lights: bool,
+    /// Color of the point lights. Each light is drawn with its own color if `None` (default)
+    pub point_light_color: Option<Color>,
+    /// Draws the outline of every [`LightOccluder2d`]
+    pub light_occluders: bool,
+    /// Color of the light occluders
+    pub light_occluder_color: Color,
+    /// Draws the outline of every [`FogVolume2d`]
+    pub fog_volumes: bool,
+    /// Color of the fog volumes. Each volume is drawn with its own color if `None` (default)
+    pub fog_volume_color: Option<Color>,
+}
+
+impl Default for Lighting2dGizmoConfigGroup {
+    fn default() -> Self {
+        Self {
+            point_lights: true,
+            point_light_color: None,
+            light_occluders: true,
+            light_occluder_color: Color::srgb(1.0, 0.0, 1.0),
+            fog_volumes: true,
+            fog_volume_color: None,
+        }
+    }
+}
+
+fn draw_point_light_gizmos(
+    mut gizmos: Gizmos<Lighting2dGizmoConfigGroup>,
+    point_lights_query: Query<(&PointLight2d, &GlobalTransform, &InheritedVisibility)>,
+) {
+    if !gizmos.config_ext.point_lights {
+        return;
+    }
+
+    let color_override = gizmos.config_ext.point_light_color;
+
+    for (point_light, transform, visibility) in &point_lights_query {
+        if !visibility.get() {
+            continue;
+        }
+
+        let center = transform.translation().xy();
+        let color = color_override.unwrap_or(point_light.color);
+
+        gizmos.circle_2d(center, point_light.radius, color);
+
+        if point_light.source_radius > 0.0 {
+            gizmos.circle_2d(center, point_light.source_radius, color);
+        }
+    }
+}
+
+fn draw_light_occluder_gizmos(
+    mut gizmos: Gizmos<Lighting2dGizmoConfigGroup>,
+    light_occluders_query: Query<(&LightOccluder2d, &GlobalTransform, &InheritedVisibility)>,
+) {
+    if !gizmos.config_ext.light_occluders {
+        return;
+    }
+
+    let color = gizmos.config_ext.light_occluder_color;
+
+    for (light_occluder, transform, visibility) in &light_occluders_query {
+        if !visibility.get() {
+            continue;
+        }
+
+        // Occluders are axis aligned, only the translation is used by the lighting
+        gizmos.rect_2d(
+            transform.translation().xy(),
+            0.0,
+            light_occluder.half_size * 2.0,
+            color,
+        );
+    }
+}
+
+fn draw_fog_volume_gizmos(
+    mut gizmos: Gizmos<Lighting2dGizmoConfigGroup>,
+    fog_volumes_query: Query<(&FogVolume2d, &GlobalTransform, &InheritedVisibility)>,
+) {
+    if !gizmos.config_ext.fog_volumes {
+        return;
+    }
+
+    let color_override = gizmos.config_ext.fog_volume_color;
+
+    for (fog_volume, transform, visibility) in &fog_volumes_query {
+        if !visibility.get() {
+            continue;
+        }
+
+        let center = transform.translation().xy();
+        let color = color_override.unwrap_or(fog_volume.color);
+
+        match fog_volume.shape {
+            FogShape2d::Rect { half_size } => gizmos.rect_2d(center, 0.0, half_size * 2.0, color),
+            FogShape2d::Circle { radius } => {
+                gizmos.circle_2d(center, radius, color);
+            }
+        }
+    }
+}
@@ -1,10 +1,28 @@
+mod day_night;
 mod extract;
+#[cfg(feature = "gizmos")]
+mod gizmos;
 mod pipeline;
 mod plugin;
 mod prepare;
+mod profile;
+mod query;
+mod readback;
+mod spatial;
 mod types;
+mod visibility;
 
 pub mod prelude {
+    pub use crate::day_night::{DayNightCycle2d, DayNightGradient, DayNightValue, HOURS_PER_DAY};
+    #[cfg(feature = "gizmos")]
+    pub use crate::gizmos::{Lighting2dGizmoConfigGroup, Lighting2dGizmosPlugin};
     pub use crate::plugin::*;
+    pub use crate::profile::{
+        LightingProfile2d, LightingProfile2dLoader, LightingProfile2dLoaderError,
+    };
+    pub use crate::query::LightingQuery2d;
+    pub use crate::readback::Illuminated2d;
+    pub use crate::spatial::{OccluderBvh2d, OccluderRayHit2d};
     pub use crate::types::*;
+    pub use crate::visibility::{visibility_polygon, FieldOfView2d, VisibilityPolygon2d};
 }
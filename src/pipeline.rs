@@ -7,11 +7,14 @@ use bevy::{
         render_graph::{NodeRunError, RenderGraphContext, RenderLabel, ViewNode},
         render_resource::{
             binding_types::{sampler, texture_2d, uniform_buffer},
-            BindGroupEntries, BindGroupLayout, BindGroupLayoutEntries, CachedRenderPipelineId,
-            ColorTargetState, ColorWrites, FragmentState, GpuArrayBuffer, LoadOp, MultisampleState,
-            Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
-            RenderPassDescriptor, RenderPipelineDescriptor, SamplerBindingType, SamplerDescriptor,
-            ShaderStages, SpecializedRenderPipeline, StoreOp, TextureFormat, TextureSampleType,
+            BindGroupLayout, BindGroupLayoutEntries, BlendComponent, BlendFactor, BlendOperation,
+            BlendState, CachedRenderPipelineId, ColorTargetState, ColorWrites, Extent3d,
+            FragmentState, ImageCopyBuffer, ImageCopyTexture, ImageDataLayout, LoadOp,
+            MultisampleState, Operations, Origin3d, PipelineCache, PrimitiveState,
+            PrimitiveTopology, RenderPassColorAttachment, RenderPassDescriptor,
+            RenderPipelineDescriptor, Sampler, SamplerBindingType, SamplerDescriptor, ShaderDefVal,
+            ShaderStages, SpecializedRenderPipeline, StoreOp, TextureAspect, TextureFormat,
+            TextureSampleType, VertexState,
         },
         renderer::{RenderContext, RenderDevice},
         texture::BevyDefault,
@@ -20,11 +23,19 @@ use bevy::{
 };
 
 use crate::{
-    extract::{ExtractedLightOccluder2d, ExtractedLighting2dSettings, ExtractedPointLight2d},
+    extract::{
+        ExtractedFogVolume2d, ExtractedLightOccluder2d, ExtractedLighting2dSettings,
+        ExtractedPointLight2d, ExtractedTemporalAccumulation,
+    },
+    prelude::{Lighting2dDebug, Lighting2dSettings, LightingStrategy, UniformArrayLengths},
     prepare::{
-        Lighting2dAuxiliaryTextures, Lighting2dPostProcessPipelineId, Lighting2dSurfaceBindGroups,
+        GiCascadeUniform, Lighting2dAuxiliaryTextures, Lighting2dPostProcessPipelineId,
+        Lighting2dSurfaceBindGroups, LightingArrayBuffer, ViewGiCascadeOffsets,
+        ViewPointLightBuffers,
     },
+    readback::{ViewIlluminationReadback, READBACK_TEXEL_STRIDE},
 };
+use std::sync::atomic::Ordering;
 
 pub const TYPES_SHADER: Handle<Shader> = Handle::weak_from_u128(76578417911493);
 pub const VIEW_TRANSFORMATIONS_SHADER: Handle<Shader> = Handle::weak_from_u128(43290875047924);
@@ -32,12 +43,85 @@ pub const SDF_SHADER: Handle<Shader> = Handle::weak_from_u128(57492774892945);
 pub const LIGHTING_SHADER: Handle<Shader> = Handle::weak_from_u128(47320975447604);
 pub const BLUR_SHADER: Handle<Shader> = Handle::weak_from_u128(43806754295913);
 pub const POST_PROCESS_SHADER: Handle<Shader> = Handle::weak_from_u128(57420546547174);
+pub const TEMPORAL_SHADER: Handle<Shader> = Handle::weak_from_u128(61834092750126);
+pub const GI_SHADER: Handle<Shader> = Handle::weak_from_u128(29475108346275);
+pub const AMBIENT_OCCLUSION_SHADER: Handle<Shader> = Handle::weak_from_u128(83920461175832);
+
+/// Largest finite half float. The SDF texture is cleared to it, so it's the distance reported
+/// when there are no occluders.
+pub const MAX_DISTANCE: f32 = 65504.0;
+
+const ADDITIVE_BLEND: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Add,
+    },
+};
+
+/// Subtracts the output from the target
+const SUBTRACT_BLEND: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::ReverseSubtract,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::ReverseSubtract,
+    },
+};
+
+const MIN_BLEND: BlendState = BlendState {
+    color: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Min,
+    },
+    alpha: BlendComponent {
+        src_factor: BlendFactor::One,
+        dst_factor: BlendFactor::One,
+        operation: BlendOperation::Min,
+    },
+};
 
+/// Texture formats and uniform array lengths of the [`Lighting2dPlugin`]
+///
+/// [`Lighting2dPlugin`]: crate::prelude::Lighting2dPlugin
+#[derive(Resource, Clone, Copy)]
+pub struct Lighting2dRenderConfig {
+    pub sdf_format: TextureFormat,
+    pub lighting_format: TextureFormat,
+    pub uniform_array_lengths: UniformArrayLengths,
+}
+
+impl Lighting2dRenderConfig {
+    fn shader_defs(&self) -> Vec<ShaderDefVal> {
+        let lengths = self.uniform_array_lengths;
+
+        vec![
+            ShaderDefVal::UInt("MAX_LIGHTS".into(), lengths.point_lights as u32),
+            ShaderDefVal::UInt("MAX_OCCLUDERS".into(), lengths.occluders as u32),
+            ShaderDefVal::UInt("MAX_FOG_VOLUMES".into(), lengths.fog_volumes as u32),
+        ]
+    }
+}
+
+/// Fullscreen pipeline writing to a lighting texture
 fn create_pipeline_descriptor(
     pipeline_cache: &PipelineCache,
+    config: &Lighting2dRenderConfig,
     label: &'static str,
     layout: &BindGroupLayout,
     shader: Handle<Shader>,
+    entry_point: &'static str,
+    blend: Option<BlendState>,
 ) -> CachedRenderPipelineId {
     pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
         label: Some(label.into()),
@@ -45,11 +129,11 @@ fn create_pipeline_descriptor(
         vertex: fullscreen_shader_vertex_state(),
         fragment: Some(FragmentState {
             shader,
-            shader_defs: vec![],
-            entry_point: "fragment".into(),
+            shader_defs: config.shader_defs(),
+            entry_point: entry_point.into(),
             targets: vec![Some(ColorTargetState {
-                format: TextureFormat::Rgba16Float,
-                blend: None,
+                format: config.lighting_format,
+                blend,
                 write_mask: ColorWrites::ALL,
             })],
         }),
@@ -66,14 +150,35 @@ pub struct Lighting2dPrepassPipelines {
     pub sdf_pipeline: CachedRenderPipelineId,
     pub lighting_layout: BindGroupLayout,
     pub lighting_pipeline: CachedRenderPipelineId,
+    pub light_volume_pipeline: CachedRenderPipelineId,
+    pub debug_lighting_pipeline: CachedRenderPipelineId,
+    pub debug_light_volume_pipeline: CachedRenderPipelineId,
+    pub material_pipeline: CachedRenderPipelineId,
+    pub ambient_occlusion_layout: BindGroupLayout,
+    pub ambient_occlusion_pipeline: CachedRenderPipelineId,
+    pub gi_trace_layout: BindGroupLayout,
+    pub gi_trace_pipeline: CachedRenderPipelineId,
+    pub gi_apply_layout: BindGroupLayout,
+    pub gi_apply_pipeline: CachedRenderPipelineId,
+    pub temporal_layout: BindGroupLayout,
+    pub temporal_pipeline: CachedRenderPipelineId,
     pub blur_layout: BindGroupLayout,
-    pub blur_pipeline: CachedRenderPipelineId,
+    pub gaussian_horizontal_pipeline: CachedRenderPipelineId,
+    pub gaussian_vertical_pipeline: CachedRenderPipelineId,
+    pub bilateral_horizontal_pipeline: CachedRenderPipelineId,
+    pub bilateral_vertical_pipeline: CachedRenderPipelineId,
+    pub kawase_downsample_pipeline: CachedRenderPipelineId,
+    pub kawase_upsample_pipeline: CachedRenderPipelineId,
+    /// Shared by every pass sampling the SDF or lighting textures
+    pub sampler: Sampler,
 }
 
 impl FromWorld for Lighting2dPrepassPipelines {
     fn from_world(world: &mut World) -> Self {
         let render_device = world.resource::<RenderDevice>();
         let pipeline_cache = world.resource::<PipelineCache>();
+        let config = world.resource::<Lighting2dRenderConfig>();
+        let lengths = config.uniform_array_lengths;
 
         let sdf_layout = render_device.create_bind_group_layout(
             "sdf_bind_group_layout",
@@ -81,33 +186,247 @@ impl FromWorld for Lighting2dPrepassPipelines {
                 ShaderStages::FRAGMENT,
                 (
                     uniform_buffer::<ViewUniform>(true),
-                    GpuArrayBuffer::<ExtractedLightOccluder2d>::binding_layout(render_device),
+                    uniform_buffer::<ExtractedLighting2dSettings>(true),
+                    LightingArrayBuffer::<ExtractedLightOccluder2d>::binding_layout(
+                        render_device,
+                        lengths.occluders,
+                    ),
                 ),
             ),
         );
 
-        let sdf_pipeline =
-            create_pipeline_descriptor(pipeline_cache, "sdf_pipeline", &sdf_layout, SDF_SHADER);
+        let sdf_pipeline = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("sdf_pipeline".into()),
+            layout: vec![sdf_layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: SDF_SHADER,
+                shader_defs: config.shader_defs(),
+                entry_point: "fragment".into(),
+                targets: vec![Some(ColorTargetState {
+                    format: config.sdf_format,
+                    blend: Some(MIN_BLEND),
+                    write_mask: ColorWrites::ALL,
+                })],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+        });
 
         let lighting_layout = render_device.create_bind_group_layout(
             "lighting_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::VERTEX_FRAGMENT,
+                (
+                    uniform_buffer::<ViewUniform>(true),
+                    uniform_buffer::<ExtractedLighting2dSettings>(true),
+                    LightingArrayBuffer::<ExtractedPointLight2d>::binding_layout(
+                        render_device,
+                        lengths.point_lights,
+                    ),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                    // Blue noise
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    LightingArrayBuffer::<ExtractedFogVolume2d>::binding_layout(
+                        render_device,
+                        lengths.fog_volumes,
+                    ),
+                ),
+            ),
+        );
+
+        // Surface lighting and scattering, both added up
+        let lighting_target = Some(ColorTargetState {
+            format: config.lighting_format,
+            blend: Some(ADDITIVE_BLEND),
+            write_mask: ColorWrites::ALL,
+        });
+
+        // Fullscreen and light volume pipelines. The debug variants also record the raymarch steps
+        // and light count of each pixel for [`Lighting2dDebug`]
+        let lighting_pipelines = |debug_stats: bool| {
+            let mut shader_defs = config.shader_defs();
+            let mut targets = vec![lighting_target.clone(), lighting_target.clone()];
+
+            if debug_stats {
+                shader_defs.push("DEBUG_STATS".into());
+                targets.push(lighting_target.clone());
+            }
+
+            let lighting_pipeline =
+                pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("lighting_pipeline".into()),
+                    layout: vec![lighting_layout.clone()],
+                    vertex: fullscreen_shader_vertex_state(),
+                    fragment: Some(FragmentState {
+                        shader: LIGHTING_SHADER,
+                        shader_defs: shader_defs.clone(),
+                        entry_point: "fragment".into(),
+                        targets: targets.clone(),
+                    }),
+                    primitive: PrimitiveState::default(),
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                });
+
+            // Point lights drawn as instanced quads covering their radius
+            let light_volume_pipeline =
+                pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                    label: Some("light_volume_pipeline".into()),
+                    layout: vec![lighting_layout.clone()],
+                    vertex: VertexState {
+                        shader: LIGHTING_SHADER,
+                        shader_defs: shader_defs.clone(),
+                        entry_point: "light_volume_vertex".into(),
+                        buffers: vec![],
+                    },
+                    fragment: Some(FragmentState {
+                        shader: LIGHTING_SHADER,
+                        shader_defs,
+                        entry_point: "light_volume_fragment".into(),
+                        targets,
+                    }),
+                    primitive: PrimitiveState {
+                        topology: PrimitiveTopology::TriangleStrip,
+                        ..default()
+                    },
+                    depth_stencil: None,
+                    multisample: MultisampleState::default(),
+                    push_constant_ranges: vec![],
+                });
+
+            (lighting_pipeline, light_volume_pipeline)
+        };
+
+        let (lighting_pipeline, light_volume_pipeline) = lighting_pipelines(false);
+        let (debug_lighting_pipeline, debug_light_volume_pipeline) = lighting_pipelines(true);
+
+        // Occluder albedo and emissive colors for global illumination
+        let material_target = Some(ColorTargetState {
+            format: config.lighting_format,
+            blend: None,
+            write_mask: ColorWrites::ALL,
+        });
+
+        let material_pipeline = pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+            label: Some("material_pipeline".into()),
+            layout: vec![sdf_layout.clone()],
+            vertex: fullscreen_shader_vertex_state(),
+            fragment: Some(FragmentState {
+                shader: SDF_SHADER,
+                shader_defs: config.shader_defs(),
+                entry_point: "material_fragment".into(),
+                targets: vec![material_target.clone(), material_target],
+            }),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            push_constant_ranges: vec![],
+        });
+
+        let gi_apply_entries = (
+            uniform_buffer::<ViewUniform>(true),
+            uniform_buffer::<ExtractedLighting2dSettings>(true),
+            uniform_buffer::<GiCascadeUniform>(true),
+            sampler(SamplerBindingType::Filtering),
+            // Cascade
+            texture_2d(TextureSampleType::Float { filterable: true }),
+        );
+
+        let gi_apply_layout = render_device.create_bind_group_layout(
+            "gi_apply_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(ShaderStages::FRAGMENT, gi_apply_entries),
+        );
+
+        let gi_trace_layout = render_device.create_bind_group_layout(
+            "gi_trace_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    gi_apply_entries.0,
+                    gi_apply_entries.1,
+                    gi_apply_entries.2,
+                    gi_apply_entries.3,
+                    gi_apply_entries.4,
+                    // SDF, lighting, albedo and emissive
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                ),
+            ),
+        );
+
+        let gi_trace_pipeline = create_pipeline_descriptor(
+            pipeline_cache,
+            config,
+            "gi_trace_pipeline",
+            &gi_trace_layout,
+            GI_SHADER,
+            "trace",
+            None,
+        );
+
+        let gi_apply_pipeline = create_pipeline_descriptor(
+            pipeline_cache,
+            config,
+            "gi_apply_pipeline",
+            &gi_apply_layout,
+            GI_SHADER,
+            "apply",
+            Some(ADDITIVE_BLEND),
+        );
+
+        let ambient_occlusion_layout = render_device.create_bind_group_layout(
+            "ambient_occlusion_bind_group_layout",
             &BindGroupLayoutEntries::sequential(
                 ShaderStages::FRAGMENT,
                 (
                     uniform_buffer::<ViewUniform>(true),
                     uniform_buffer::<ExtractedLighting2dSettings>(true),
-                    GpuArrayBuffer::<ExtractedPointLight2d>::binding_layout(render_device),
                     texture_2d(TextureSampleType::Float { filterable: true }),
                     sampler(SamplerBindingType::Filtering),
                 ),
             ),
         );
 
-        let lighting_pipeline = create_pipeline_descriptor(
+        // Removes the occluded ambient light from the lighting texture
+        let ambient_occlusion_pipeline = create_pipeline_descriptor(
+            pipeline_cache,
+            config,
+            "ambient_occlusion_pipeline",
+            &ambient_occlusion_layout,
+            AMBIENT_OCCLUSION_SHADER,
+            "fragment",
+            Some(SUBTRACT_BLEND),
+        );
+
+        let temporal_layout = render_device.create_bind_group_layout(
+            "temporal_bind_group_layout",
+            &BindGroupLayoutEntries::sequential(
+                ShaderStages::FRAGMENT,
+                (
+                    uniform_buffer::<ViewUniform>(true),
+                    uniform_buffer::<ExtractedTemporalAccumulation>(true),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    texture_2d(TextureSampleType::Float { filterable: true }),
+                    sampler(SamplerBindingType::Filtering),
+                ),
+            ),
+        );
+
+        let temporal_pipeline = create_pipeline_descriptor(
             pipeline_cache,
-            "lighting_pipeline",
-            &lighting_layout,
-            LIGHTING_SHADER,
+            config,
+            "temporal_pipeline",
+            &temporal_layout,
+            TEMPORAL_SHADER,
+            "fragment",
+            None,
         );
 
         let blur_layout = render_device.create_bind_group_layout(
@@ -119,20 +438,61 @@ impl FromWorld for Lighting2dPrepassPipelines {
                     uniform_buffer::<ExtractedLighting2dSettings>(true),
                     texture_2d(TextureSampleType::Float { filterable: true }),
                     sampler(SamplerBindingType::Filtering),
+                    // SDF, for the bilateral blur
+                    texture_2d(TextureSampleType::Float { filterable: true }),
                 ),
             ),
         );
 
-        let blur_pipeline =
-            create_pipeline_descriptor(pipeline_cache, "blur_pipeline", &blur_layout, BLUR_SHADER);
+        let blur_pipeline = |label, entry_point| {
+            create_pipeline_descriptor(
+                pipeline_cache,
+                config,
+                label,
+                &blur_layout,
+                BLUR_SHADER,
+                entry_point,
+                None,
+            )
+        };
+
+        let gaussian_horizontal_pipeline =
+            blur_pipeline("gaussian_horizontal_pipeline", "gaussian_horizontal");
+        let gaussian_vertical_pipeline =
+            blur_pipeline("gaussian_vertical_pipeline", "gaussian_vertical");
+        let bilateral_horizontal_pipeline =
+            blur_pipeline("bilateral_horizontal_pipeline", "bilateral_horizontal");
+        let bilateral_vertical_pipeline =
+            blur_pipeline("bilateral_vertical_pipeline", "bilateral_vertical");
+        let kawase_downsample_pipeline =
+            blur_pipeline("kawase_downsample_pipeline", "kawase_downsample");
+        let kawase_upsample_pipeline = blur_pipeline("kawase_upsample_pipeline", "kawase_upsample");
 
         Self {
             sdf_layout,
             sdf_pipeline,
             lighting_layout,
             lighting_pipeline,
+            light_volume_pipeline,
+            debug_lighting_pipeline,
+            debug_light_volume_pipeline,
+            material_pipeline,
+            gi_trace_layout,
+            gi_trace_pipeline,
+            gi_apply_layout,
+            gi_apply_pipeline,
+            ambient_occlusion_layout,
+            ambient_occlusion_pipeline,
+            temporal_layout,
+            temporal_pipeline,
             blur_layout,
-            blur_pipeline,
+            gaussian_horizontal_pipeline,
+            gaussian_vertical_pipeline,
+            bilateral_horizontal_pipeline,
+            bilateral_vertical_pipeline,
+            kawase_downsample_pipeline,
+            kawase_upsample_pipeline,
+            sampler: render_device.create_sampler(&SamplerDescriptor::default()),
         }
     }
 }
@@ -140,6 +500,7 @@ impl FromWorld for Lighting2dPrepassPipelines {
 #[derive(Resource)]
 pub struct PostProcessPipeline {
     pub layout: BindGroupLayout,
+    pub sampler: Sampler,
 }
 
 impl FromWorld for PostProcessPipeline {
@@ -154,10 +515,17 @@ impl FromWorld for PostProcessPipeline {
                     (
                         texture_2d(TextureSampleType::Float { filterable: true }),
                         texture_2d(TextureSampleType::Float { filterable: true }),
+                        // Scattering
+                        texture_2d(TextureSampleType::Float { filterable: true }),
                         sampler(SamplerBindingType::Filtering),
+                        // SDF, unblurred lighting and stats, only read by the debug views
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        texture_2d(TextureSampleType::Float { filterable: true }),
+                        texture_2d(TextureSampleType::Float { filterable: true }),
                     ),
                 ),
             ),
+            sampler: render_device.create_sampler(&SamplerDescriptor::default()),
         }
     }
 }
@@ -165,6 +533,7 @@ impl FromWorld for PostProcessPipeline {
 #[derive(Eq, PartialEq, Hash, Clone, Copy)]
 pub struct Lighting2dPipelineKey {
     pub hdr: bool,
+    pub debug: Option<Lighting2dDebug>,
 }
 
 impl SpecializedRenderPipeline for PostProcessPipeline {
@@ -177,7 +546,21 @@ impl SpecializedRenderPipeline for PostProcessPipeline {
             vertex: fullscreen_shader_vertex_state(),
             fragment: Some(FragmentState {
                 shader: POST_PROCESS_SHADER,
-                shader_defs: vec![],
+                shader_defs: key
+                    .debug
+                    .map(|debug| {
+                        match debug {
+                            Lighting2dDebug::Sdf => "DEBUG_SDF",
+                            Lighting2dDebug::Lighting => "DEBUG_LIGHTING",
+                            Lighting2dDebug::BlurComparison => "DEBUG_BLUR_COMPARISON",
+                            Lighting2dDebug::RaymarchSteps => "DEBUG_RAYMARCH_STEPS",
+                            Lighting2dDebug::LightCount => "DEBUG_LIGHT_COUNT",
+                            Lighting2dDebug::OccluderOutlines => "DEBUG_OCCLUDER_OUTLINES",
+                        }
+                        .into()
+                    })
+                    .into_iter()
+                    .collect(),
                 entry_point: "fragment".into(),
                 targets: vec![Some(ColorTargetState {
                     format: if key.hdr {
@@ -210,12 +593,17 @@ impl ViewNode for LightingNode {
         Read<Lighting2dPostProcessPipelineId>,
         Read<Lighting2dAuxiliaryTextures>,
         Read<Lighting2dSurfaceBindGroups>,
+        Read<Lighting2dSettings>,
+        Read<ExtractedLighting2dSettings>,
         Read<DynamicUniformIndex<ExtractedLighting2dSettings>>,
+        Option<Read<DynamicUniformIndex<ExtractedTemporalAccumulation>>>,
+        Option<Read<ViewGiCascadeOffsets>>,
+        Option<Read<ViewIlluminationReadback>>,
     );
 
     fn run<'w>(
         &self,
-        _: &mut RenderGraphContext,
+        graph: &mut RenderGraphContext,
         ctx: &mut RenderContext<'w>,
         (
             view_target,
@@ -223,84 +611,345 @@ impl ViewNode for LightingNode {
             post_process_pipeline_id,
             aux_textures,
             bind_groups,
+            settings,
+            extracted_settings,
             settings_index,
+            temporal_index,
+            gi_cascade_offsets,
+            illumination_readback,
         ): QueryItem<'w, Self::ViewQuery>,
         world: &'w World,
     ) -> Result<(), NodeRunError> {
         let pipeline_cache = world.resource::<PipelineCache>();
         let prepass_pipelines = world.resource::<Lighting2dPrepassPipelines>();
+        let point_lights = world
+            .resource::<ViewPointLightBuffers>()
+            .get(&graph.view_entity())
+            .unwrap_or(world.resource::<LightingArrayBuffer<ExtractedPointLight2d>>());
+        let light_occluders = world.resource::<LightingArrayBuffer<ExtractedLightOccluder2d>>();
+        let fog_volumes = world.resource::<LightingArrayBuffer<ExtractedFogVolume2d>>();
+
+        let lighting_pipeline = match (settings.strategy, &aux_textures.debug_stats) {
+            (LightingStrategy::Fullscreen, None) => prepass_pipelines.lighting_pipeline,
+            (LightingStrategy::LightVolumes, None) => prepass_pipelines.light_volume_pipeline,
+            (LightingStrategy::Fullscreen, Some(_)) => prepass_pipelines.debug_lighting_pipeline,
+            (LightingStrategy::LightVolumes, Some(_)) => {
+                prepass_pipelines.debug_light_volume_pipeline
+            }
+        };
 
         let (
             Some(sdf_pipeline),
             Some(lighting_pipeline),
-            Some(blur_pipeline),
+            Some(blur_pipelines),
             Some(post_process_pipeline),
+            Some((_, post_process_bind_group)),
         ) = (
             pipeline_cache.get_render_pipeline(prepass_pipelines.sdf_pipeline),
-            pipeline_cache.get_render_pipeline(prepass_pipelines.lighting_pipeline),
-            pipeline_cache.get_render_pipeline(prepass_pipelines.blur_pipeline),
+            pipeline_cache.get_render_pipeline(lighting_pipeline),
+            bind_groups
+                .blur
+                .iter()
+                .map(|pass| pipeline_cache.get_render_pipeline(pass.pipeline))
+                .collect::<Option<Vec<_>>>(),
             pipeline_cache.get_render_pipeline(post_process_pipeline_id.0),
+            // The nodes before this one may have swapped the main textures since it was prepared
+            bind_groups
+                .post_process
+                .iter()
+                .find(|(source, _)| *source == view_target.main_texture_view().id()),
         )
         else {
             return Ok(());
         };
 
-        let storage_buffer_support = ctx
-            .render_device()
-            .limits()
-            .max_storage_buffers_per_shader_stage
-            > 0;
+        // The optional passes are skipped until their pipelines are compiled
+        let gi = match (&bind_groups.gi, &aux_textures.gi, gi_cascade_offsets) {
+            (Some(bind_groups), Some(textures), Some(offsets)) => match (
+                pipeline_cache.get_render_pipeline(prepass_pipelines.material_pipeline),
+                pipeline_cache.get_render_pipeline(prepass_pipelines.gi_trace_pipeline),
+                pipeline_cache.get_render_pipeline(prepass_pipelines.gi_apply_pipeline),
+            ) {
+                (Some(material_pipeline), Some(trace_pipeline), Some(apply_pipeline)) => Some((
+                    material_pipeline,
+                    trace_pipeline,
+                    apply_pipeline,
+                    bind_groups,
+                    textures,
+                    offsets,
+                )),
+                _ => None,
+            },
+            _ => None,
+        };
+
+        let ambient_occlusion = bind_groups
+            .ambient_occlusion
+            .as_ref()
+            .and_then(|bind_group| {
+                pipeline_cache
+                    .get_render_pipeline(prepass_pipelines.ambient_occlusion_pipeline)
+                    .map(|pipeline| (pipeline, bind_group))
+            });
+
+        let temporal = match (&bind_groups.temporal, &aux_textures.history, temporal_index) {
+            (Some(bind_group), Some(history), Some(temporal_index)) => Some((
+                pipeline_cache.get_render_pipeline(prepass_pipelines.temporal_pipeline),
+                bind_group,
+                history,
+                temporal_index,
+            )),
+            _ => None,
+        };
 
-        // SDF
+        // SDF, the distance of each occluder batch is min blended
         let mut sdf_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
             label: Some("sdf_pass"),
             color_attachments: &[Some(RenderPassColorAttachment {
                 view: &aux_textures.sdf.default_view,
                 resolve_target: None,
-                ops: Operations::default(),
+                ops: Operations {
+                    load: LoadOp::Clear(LinearRgba::gray(MAX_DISTANCE).into()),
+                    store: StoreOp::Store,
+                },
             })],
             ..default()
         });
 
-        let mut dynamic_offset = vec![view_uniform.offset];
-        if !storage_buffer_support {
-            dynamic_offset.push(0);
-        }
-
         sdf_pass.set_render_pipeline(sdf_pipeline);
-        sdf_pass.set_bind_group(0, &bind_groups.sdf, &dynamic_offset[..]);
-        sdf_pass.draw(0..3, 0..1);
+
+        for batch in light_occluders.batches() {
+            let mut dynamic_offsets = vec![view_uniform.offset, settings_index.index()];
+            dynamic_offsets.extend(batch);
+
+            sdf_pass.set_bind_group(0, &bind_groups.sdf, &dynamic_offsets);
+            sdf_pass.draw(0..3, 0..1);
+        }
 
         drop(sdf_pass);
 
-        // Lighting
-        let mut lighting_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
-            label: Some("lighting_pass"),
-            color_attachments: &[Some(RenderPassColorAttachment {
+        // Lighting, each point light batch is added on top of the ambient light
+        let clear_none = Operations {
+            load: LoadOp::Clear(LinearRgba::NONE.into()),
+            store: StoreOp::Store,
+        };
+
+        let mut lighting_attachments = vec![
+            Some(RenderPassColorAttachment {
                 view: &aux_textures.lighting.default_view,
                 resolve_target: None,
-                ops: Operations::default(),
-            })],
-            ..default()
-        });
+                ops: Operations {
+                    load: LoadOp::Clear(extracted_settings.ambient_light.with_alpha(1.0).into()),
+                    store: StoreOp::Store,
+                },
+            }),
+            Some(RenderPassColorAttachment {
+                view: &aux_textures.scattering.default_view,
+                resolve_target: None,
+                ops: clear_none,
+            }),
+        ];
 
-        let mut dynamic_offset = vec![view_uniform.offset, settings_index.index()];
-        if !storage_buffer_support {
-            dynamic_offset.push(0);
+        // Only the debug pipelines have a stats target
+        if let Some(debug_stats) = &aux_textures.debug_stats {
+            lighting_attachments.push(Some(RenderPassColorAttachment {
+                view: &debug_stats.default_view,
+                resolve_target: None,
+                ops: clear_none,
+            }));
         }
 
+        let mut lighting_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+            label: Some("lighting_pass"),
+            color_attachments: &lighting_attachments,
+            ..default()
+        });
+
         lighting_pass.set_render_pipeline(lighting_pipeline);
-        lighting_pass.set_bind_group(0, &bind_groups.lighting, &dynamic_offset[..]);
-        lighting_pass.draw(0..3, 0..1);
+
+        for batch in point_lights.batches() {
+            let mut dynamic_offsets = vec![view_uniform.offset, settings_index.index()];
+            dynamic_offsets.extend(batch);
+            // Fog volumes past the first batch are ignored, see `ExtractedFogVolume2d::BATCHED`
+            dynamic_offsets.extend(fog_volumes.batches().next().flatten());
+
+            lighting_pass.set_bind_group(0, &bind_groups.lighting, &dynamic_offsets);
+
+            match settings.strategy {
+                LightingStrategy::Fullscreen => lighting_pass.draw(0..3, 0..1),
+                LightingStrategy::LightVolumes => {
+                    // Padding lights of uniform batches have no radius and produce empty quads
+                    let instances = match batch {
+                        Some(_) => point_lights.array_len() as u32,
+                        None => extracted_settings.point_light_count,
+                    };
+
+                    lighting_pass.draw(0..4, 0..instances);
+                }
+            }
+        }
 
         drop(lighting_pass);
 
-        // Blur
-        if let Some(blur_texture) = &aux_textures.blur {
+        // Global illumination
+        if let Some((
+            material_pipeline,
+            trace_pipeline,
+            apply_pipeline,
+            gi_bind_groups,
+            textures,
+            offsets,
+        )) = gi
+        {
+            // Occluder materials, each occluder batch overwrites the fragments it covers
+            let clear = Operations {
+                load: LoadOp::Clear(LinearRgba::NONE.into()),
+                store: StoreOp::Store,
+            };
+
+            let mut material_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("material_pass"),
+                color_attachments: &[
+                    Some(RenderPassColorAttachment {
+                        view: &textures.albedo.default_view,
+                        resolve_target: None,
+                        ops: clear,
+                    }),
+                    Some(RenderPassColorAttachment {
+                        view: &textures.emissive.default_view,
+                        resolve_target: None,
+                        ops: clear,
+                    }),
+                ],
+                ..default()
+            });
+
+            material_pass.set_render_pipeline(material_pipeline);
+
+            for batch in light_occluders.batches() {
+                let mut dynamic_offsets = vec![view_uniform.offset, settings_index.index()];
+                dynamic_offsets.extend(batch);
+
+                material_pass.set_bind_group(0, &bind_groups.sdf, &dynamic_offsets);
+                material_pass.draw(0..3, 0..1);
+            }
+
+            drop(material_pass);
+
+            // Cascades from the last to the first, each merging the one traced before it
+            for (cascade, offset) in offsets.iter().enumerate().rev() {
+                let mut trace_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+                    label: Some("gi_trace_pass"),
+                    color_attachments: &[Some(RenderPassColorAttachment {
+                        view: &textures.cascades[cascade % 2].default_view,
+                        resolve_target: None,
+                        ops: Operations {
+                            load: LoadOp::Load,
+                            store: StoreOp::Store,
+                        },
+                    })],
+                    ..default()
+                });
+
+                trace_pass.set_bind_group(
+                    0,
+                    &gi_bind_groups.trace[(cascade + 1) % 2],
+                    &[view_uniform.offset, settings_index.index(), *offset],
+                );
+                trace_pass.set_render_pipeline(trace_pipeline);
+                trace_pass.draw(0..3, 0..1);
+            }
+
+            // Indirect light added on top of the direct light
+            let mut apply_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("gi_apply_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &aux_textures.lighting.default_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..default()
+            });
+
+            apply_pass.set_bind_group(
+                0,
+                &gi_bind_groups.apply,
+                &[view_uniform.offset, settings_index.index(), offsets[0]],
+            );
+            apply_pass.set_render_pipeline(apply_pipeline);
+            apply_pass.draw(0..3, 0..1);
+        }
+
+        // Ambient occlusion, only darkens the ambient light the lighting texture was cleared to. It
+        // runs after the global illumination, which takes the ambient light out of the lighting to
+        // bounce the direct light only
+        if let Some((pipeline, bind_group)) = ambient_occlusion {
+            let mut ambient_occlusion_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+                label: Some("ambient_occlusion_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: &aux_textures.lighting.default_view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Load,
+                        store: StoreOp::Store,
+                    },
+                })],
+                ..default()
+            });
+
+            ambient_occlusion_pass.set_bind_group(
+                0,
+                bind_group,
+                &[view_uniform.offset, settings_index.index()],
+            );
+            ambient_occlusion_pass.set_render_pipeline(pipeline);
+            ambient_occlusion_pass.draw(0..3, 0..1);
+        }
+
+        // Temporal accumulation, blends the lighting with the reprojected history
+        if let Some((pipeline, bind_group, history, temporal_index)) = temporal {
+            match pipeline {
+                Some(pipeline) => {
+                    let mut temporal_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
+                        label: Some("temporal_pass"),
+                        color_attachments: &[Some(RenderPassColorAttachment {
+                            view: &history.write.default_view,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Load,
+                                store: StoreOp::Store,
+                            },
+                        })],
+                        ..default()
+                    });
+
+                    temporal_pass.set_bind_group(
+                        0,
+                        bind_group,
+                        &[view_uniform.offset, temporal_index.index()],
+                    );
+                    temporal_pass.set_render_pipeline(pipeline);
+                    temporal_pass.draw(0..3, 0..1);
+                }
+                // The passes after it read the history, which holds the unblended lighting until
+                // the pipeline is compiled
+                None => ctx.command_encoder().copy_texture_to_texture(
+                    aux_textures.lighting.texture.as_image_copy(),
+                    history.write.texture.as_image_copy(),
+                    aux_textures.lighting.texture.size(),
+                ),
+            }
+        }
+
+        // Blur, each pass reads the output of the previous one
+        for (pass, pipeline) in bind_groups.blur.iter().zip(blur_pipelines) {
             let mut blur_pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
                 label: Some("blur_pass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &blur_texture.default_view,
+                    view: &pass.target,
                     resolve_target: None,
                     ops: Operations {
                         load: LoadOp::Load,
@@ -312,34 +961,49 @@ impl ViewNode for LightingNode {
 
             blur_pass.set_bind_group(
                 0,
-                &bind_groups.blur,
+                &pass.bind_group,
                 &[view_uniform.offset, settings_index.index()],
             );
-            blur_pass.set_render_pipeline(blur_pipeline);
+            blur_pass.set_render_pipeline(pipeline);
             blur_pass.draw(0..3, 0..1);
         }
 
+        // Copies the light of the illuminated entities, mapped once the frame is submitted
+        if let Some(readback) = illumination_readback {
+            for (index, texel) in readback.texels.iter().enumerate() {
+                ctx.command_encoder().copy_texture_to_buffer(
+                    ImageCopyTexture {
+                        texture: &aux_textures.final_lighting().texture,
+                        mip_level: 0,
+                        origin: Origin3d {
+                            x: texel.x,
+                            y: texel.y,
+                            z: 0,
+                        },
+                        aspect: TextureAspect::All,
+                    },
+                    ImageCopyBuffer {
+                        buffer: &readback.buffer,
+                        layout: ImageDataLayout {
+                            offset: (index * READBACK_TEXEL_STRIDE) as u64,
+                            bytes_per_row: None,
+                            rows_per_image: None,
+                        },
+                    },
+                    Extent3d {
+                        width: 1,
+                        height: 1,
+                        depth_or_array_layers: 1,
+                    },
+                );
+            }
+
+            readback.written.store(true, Ordering::Relaxed);
+        }
+
         // Post Process
         let post_process = view_target.post_process_write();
 
-        let sampler = ctx
-            .render_device()
-            .create_sampler(&SamplerDescriptor::default());
-
-        let post_process_bind_group = ctx.render_device().create_bind_group(
-            "post_process_bind_group",
-            &world.resource::<PostProcessPipeline>().layout,
-            &BindGroupEntries::sequential((
-                post_process.source,
-                if let Some(blur_texture) = &aux_textures.blur {
-                    &blur_texture.default_view
-                } else {
-                    &aux_textures.lighting.default_view
-                },
-                &sampler,
-            )),
-        );
-
         let mut pass = ctx.begin_tracked_render_pass(RenderPassDescriptor {
             label: Some("post_process_pass"),
             color_attachments: &[Some(RenderPassColorAttachment {
@@ -350,7 +1014,7 @@ impl ViewNode for LightingNode {
             ..default()
         });
 
-        pass.set_bind_group(0, &post_process_bind_group, &[]);
+        pass.set_bind_group(0, post_process_bind_group, &[]);
         pass.set_render_pipeline(post_process_pipeline);
         pass.draw(0..3, 0..1);
 
@@ -4,40 +4,241 @@ use bevy::{
     prelude::*,
     render::{
         extract_component::UniformComponentPlugin,
-        gpu_component_array_buffer::GpuComponentArrayBufferPlugin,
         render_graph::{RenderGraphApp, ViewNodeRunner},
-        render_resource::SpecializedRenderPipelines,
+        render_resource::{SpecializedRenderPipelines, TextureFormat},
+        renderer::RenderDevice,
+        settings::WgpuFeatures,
         view::{check_visibility, prepare_view_targets, VisibilitySystems},
         Render, RenderApp, RenderSet,
     },
+    transform::TransformSystem,
 };
 
 use crate::{
+    day_night::update_day_night_cycles,
     extract::{
-        extract_light_occluders, extract_lighting_settings, extract_point_lights,
+        extract_fog_volumes, extract_light_budgets, extract_light_occluders,
+        extract_lighting_debug, extract_lighting_settings, extract_point_lights,
+        extract_temporal_accumulation, ExtractedArray, ExtractedFogVolume2d, ExtractedLightBudgets,
         ExtractedLightOccluder2d, ExtractedLighting2dSettings, ExtractedPointLight2d,
+        ExtractedTemporalAccumulation,
     },
     pipeline::{
-        Lighting2dPrepassPipelines, LightingLabel, LightingNode, PostProcessPipeline, BLUR_SHADER,
-        LIGHTING_SHADER, POST_PROCESS_SHADER, SDF_SHADER, TYPES_SHADER,
+        Lighting2dPrepassPipelines, Lighting2dRenderConfig, LightingLabel, LightingNode,
+        PostProcessPipeline, AMBIENT_OCCLUSION_SHADER, BLUR_SHADER, GI_SHADER, LIGHTING_SHADER,
+        POST_PROCESS_SHADER, SDF_SHADER, TEMPORAL_SHADER, TYPES_SHADER,
         VIEW_TRANSFORMATIONS_SHADER,
     },
-    prelude::{AmbientLight2d, LightOccluder2d, Lighting2dSettings, PointLight2d},
+    prelude::{
+        AmbientLight2d, AmbientOcclusion, BlurMode, DayNightCycle2d, DayNightGradient, FogShape2d,
+        FogVolume2d, GlobalIllumination, JitterSource, LightBudget, LightOccluder2d,
+        Lighting2dDebug, Lighting2dSettings, LightingStrategy, PointLight2d, RaymarchSettings,
+        ScatteringSettings, TemporalAccumulation,
+    },
     prepare::{
+        prepare_gi_cascade_uniforms, prepare_lighting_array_buffer,
         prepare_lighting_auxiliary_textures, prepare_lighting_bind_groups,
-        prepare_post_process_pipelines,
+        prepare_post_process_pipelines, prepare_view_point_light_buffers, BlueNoiseTexture,
+        GiCascadeUniforms, Lighting2dBindGroupCache, LightingArrayBuffer, ViewPointLightBuffers,
+        UNIFORM_ARRAY_LEN,
+    },
+    profile::{apply_lighting_profiles, LightingProfile2d, LightingProfile2dLoader},
+    readback::{
+        extract_illuminated, map_illumination_readbacks, prepare_illumination_readbacks,
+        receive_illumination_readbacks, update_illuminated, ExtractedIlluminated2d, Illuminated2d,
+        IlluminationReadbacks, IlluminationResults,
     },
+    spatial::{update_occluder_bvh, OccluderBvh2d},
 };
 
 /// A plugin for adding 2D lighting in the Bevy engine.
 ///
 /// This plugin sets up and configures the necessary components and systems for 2D lighting,
 /// including [`AmbientLight2d`], [`Lighting2dSettings`], [`PointLight2d`], and [`LightOccluder2d`].
-#[derive(Default)]
-pub struct Lighting2dPlugin;
+///
+/// Its fields configure the rendering and are validated when the plugin is built, which panics
+/// on invalid values.
+///
+/// ```no_run
+/// # use bevy::{core_pipeline::core_2d::graph::Node2d, prelude::*};
+/// # use bevy_lit::prelude::*;
+/// App::new().add_plugins((
+///     DefaultPlugins,
+///     Lighting2dPlugin {
+///         run_after: Node2d::Bloom,
+///         ..default()
+///     },
+/// ));
+/// ```
+#[derive(Clone, Debug)]
+pub struct Lighting2dPlugin {
+    /// Node of the [`Core2d`] render graph the lighting runs after. It must not run before
+    /// [`Node2d::EndMainPass`], the lighting multiplies the rendered scene. Defaults to
+    /// [`Node2d::EndMainPass`].
+    pub run_after: Node2d,
+    /// Node of the [`Core2d`] render graph the lighting runs before, like
+    /// [`Node2d::Tonemapping`]. It must come after `run_after`, and no later than
+    /// [`Node2d::Upscaling`]. Defaults to [`Node2d::Upscaling`].
+    pub run_before: Node2d,
+    /// Format of the texture holding the distance to the closest occluder, only its red channel is
+    /// used. Either [`TextureFormat::R16Float`] or [`TextureFormat::Rgba16Float`] (default).
+    pub sdf_format: TextureFormat,
+    /// Format of the lighting textures. Either [`TextureFormat::Rgba16Float`] (default) or
+    /// [`TextureFormat::Rg11b10Float`], which takes half the memory and bandwidth but requires
+    /// the `RG11B10UFLOAT_RENDERABLE` wgpu feature.
+    pub lighting_format: TextureFormat,
+    /// Array lengths of the uniform buffers used on targets without storage buffers (WebGL2)
+    pub uniform_array_lengths: UniformArrayLengths,
+}
+
+impl Default for Lighting2dPlugin {
+    fn default() -> Self {
+        Self {
+            run_after: Node2d::EndMainPass,
+            run_before: Node2d::Upscaling,
+            sdf_format: TextureFormat::Rgba16Float,
+            lighting_format: TextureFormat::Rgba16Float,
+            uniform_array_lengths: UniformArrayLengths::default(),
+        }
+    }
+}
+
+/// Number of values bound at once on targets without storage buffers, where they are drawn in
+/// batches of this length. Longer arrays take fewer passes, but each batch must fit in the
+/// `max_uniform_buffer_binding_size` limit of the device, 16KiB on WebGL2.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct UniformArrayLengths {
+    /// Point lights per batch. Defaults to 82.
+    pub point_lights: usize,
+    /// Occluders per batch. Defaults to 82.
+    pub occluders: usize,
+    /// Fog volumes, only the first batch is sampled. Defaults to 82.
+    pub fog_volumes: usize,
+}
+
+impl Default for UniformArrayLengths {
+    fn default() -> Self {
+        Self {
+            point_lights: UNIFORM_ARRAY_LEN,
+            occluders: UNIFORM_ARRAY_LEN,
+            fog_volumes: UNIFORM_ARRAY_LEN,
+        }
+    }
+}
+
+impl Lighting2dPlugin {
+    fn validate(&self) {
+        let after = graph_order(&self.run_after);
+        let before = graph_order(&self.run_before);
+
+        assert!(
+            after >= graph_order(&Node2d::EndMainPass) && after < graph_order(&Node2d::Upscaling),
+            "`Lighting2dPlugin::run_after` must be between `Node2d::EndMainPass` and \
+            `Node2d::Upscaling`, got `{:?}`",
+            self.run_after
+        );
+        assert!(
+            before > after && before <= graph_order(&Node2d::Upscaling),
+            "`Lighting2dPlugin::run_before` must come after `{:?}` and no later than \
+            `Node2d::Upscaling`, got `{:?}`",
+            self.run_after,
+            self.run_before
+        );
+        assert!(
+            matches!(
+                self.sdf_format,
+                TextureFormat::R16Float | TextureFormat::Rgba16Float
+            ),
+            "`Lighting2dPlugin::sdf_format` must be `R16Float` or `Rgba16Float`, got `{:?}`",
+            self.sdf_format
+        );
+        assert!(
+            matches!(
+                self.lighting_format,
+                TextureFormat::Rgba16Float | TextureFormat::Rg11b10Float
+            ),
+            "`Lighting2dPlugin::lighting_format` must be `Rgba16Float` or `Rg11b10Float`, got \
+            `{:?}`",
+            self.lighting_format
+        );
+
+        let lengths = self.uniform_array_lengths;
+        assert!(
+            lengths.point_lights > 0 && lengths.occluders > 0 && lengths.fog_volumes > 0,
+            "`Lighting2dPlugin::uniform_array_lengths` must not be zero, got {lengths:?}"
+        );
+    }
+
+    /// Checks the configuration against the device, once it's available
+    fn validate_device(&self, render_device: &RenderDevice) {
+        if self.lighting_format == TextureFormat::Rg11b10Float {
+            assert!(
+                render_device
+                    .features()
+                    .contains(WgpuFeatures::RG11B10UFLOAT_RENDERABLE),
+                "`Lighting2dPlugin::lighting_format` is `Rg11b10Float`, but the device doesn't \
+                support the `RG11B10UFLOAT_RENDERABLE` feature"
+            );
+        }
+
+        let limits = render_device.limits();
+
+        if limits.max_storage_buffers_per_shader_stage > 0 {
+            return;
+        }
+
+        let lengths = self.uniform_array_lengths;
+        let max_size = u64::from(limits.max_uniform_buffer_binding_size);
+
+        for (name, size) in [
+            (
+                "point_lights",
+                LightingArrayBuffer::<ExtractedPointLight2d>::uniform_batch_size(
+                    lengths.point_lights,
+                ),
+            ),
+            (
+                "occluders",
+                LightingArrayBuffer::<ExtractedLightOccluder2d>::uniform_batch_size(
+                    lengths.occluders,
+                ),
+            ),
+            (
+                "fog_volumes",
+                LightingArrayBuffer::<ExtractedFogVolume2d>::uniform_batch_size(
+                    lengths.fog_volumes,
+                ),
+            ),
+        ] {
+            assert!(
+                size <= max_size,
+                "`Lighting2dPlugin::uniform_array_lengths.{name}` takes {size} bytes, more than \
+                the {max_size} bytes uniform buffers are limited to"
+            );
+        }
+    }
+}
+
+/// Position of the nodes in the [`Core2d`] graph, nodes on parallel branches are equal
+fn graph_order(node: &Node2d) -> u32 {
+    match node {
+        Node2d::MsaaWriteback => 0,
+        Node2d::StartMainPass => 1,
+        Node2d::MainTransparentPass => 2,
+        Node2d::EndMainPass => 3,
+        Node2d::Bloom => 4,
+        Node2d::Tonemapping => 5,
+        Node2d::Fxaa | Node2d::Smaa => 6,
+        Node2d::ContrastAdaptiveSharpening => 7,
+        Node2d::EndMainPassPostProcessing => 8,
+        Node2d::Upscaling => 9,
+    }
+}
 
 impl Plugin for Lighting2dPlugin {
     fn build(&self, app: &mut App) {
+        self.validate();
+
         load_internal_asset!(app, TYPES_SHADER, "shaders/types.wgsl", Shader::from_wgsl);
         load_internal_asset!(
             app,
@@ -53,6 +254,19 @@ impl Plugin for Lighting2dPlugin {
             Shader::from_wgsl
         );
         load_internal_asset!(app, BLUR_SHADER, "shaders/blur.wgsl", Shader::from_wgsl);
+        load_internal_asset!(app, GI_SHADER, "shaders/gi.wgsl", Shader::from_wgsl);
+        load_internal_asset!(
+            app,
+            AMBIENT_OCCLUSION_SHADER,
+            "shaders/ambient_occlusion.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            TEMPORAL_SHADER,
+            "shaders/temporal.wgsl",
+            Shader::from_wgsl
+        );
         load_internal_asset!(
             app,
             POST_PROCESS_SHADER,
@@ -62,31 +276,87 @@ impl Plugin for Lighting2dPlugin {
 
         app.add_plugins((
             UniformComponentPlugin::<ExtractedLighting2dSettings>::default(),
-            GpuComponentArrayBufferPlugin::<ExtractedPointLight2d>::default(),
-            GpuComponentArrayBufferPlugin::<ExtractedLightOccluder2d>::default(),
+            UniformComponentPlugin::<ExtractedTemporalAccumulation>::default(),
         ))
         .register_type::<AmbientLight2d>()
         .register_type::<PointLight2d>()
         .register_type::<LightOccluder2d>()
         .register_type::<Lighting2dSettings>()
+        .register_type::<RaymarchSettings>()
+        .register_type::<LightBudget>()
+        .register_type::<LightingStrategy>()
+        .register_type::<BlurMode>()
+        .register_type::<JitterSource>()
+        .register_type::<TemporalAccumulation>()
+        .register_type::<GlobalIllumination>()
+        .register_type::<AmbientOcclusion>()
+        .register_type::<Lighting2dDebug>()
+        .register_type::<FogVolume2d>()
+        .register_type::<FogShape2d>()
+        .register_type::<DayNightCycle2d>()
+        .register_type::<DayNightGradient<Color>>()
+        .register_type::<DayNightGradient<f32>>()
+        .register_type::<ScatteringSettings>()
+        .register_type::<Illuminated2d>()
+        .register_type::<LightingProfile2d>()
+        .init_asset::<LightingProfile2d>()
+        .init_asset_loader::<LightingProfile2dLoader>()
+        .init_resource::<OccluderBvh2d>()
+        .add_systems(Update, (update_day_night_cycles, apply_lighting_profiles))
         .add_systems(
             PostUpdate,
-            check_visibility::<Or<(With<PointLight2d>, With<LightOccluder2d>)>>
-                .in_set(VisibilitySystems::CheckVisibility),
+            (
+                check_visibility::<
+                    Or<(With<PointLight2d>, With<LightOccluder2d>, With<FogVolume2d>)>,
+                >
+                    .in_set(VisibilitySystems::CheckVisibility),
+                update_occluder_bvh
+                    .after(TransformSystem::TransformPropagate)
+                    .after(VisibilitySystems::VisibilityPropagate),
+            ),
         );
 
+        let illumination_results = IlluminationResults::default();
+
+        app.insert_resource(illumination_results.clone())
+            .add_systems(PreUpdate, update_illuminated);
+
         let Some(render_app) = app.get_sub_app_mut(RenderApp) else {
             return;
         };
 
         render_app
+            .insert_resource(illumination_results)
+            .insert_resource(Lighting2dRenderConfig {
+                sdf_format: self.sdf_format,
+                lighting_format: self.lighting_format,
+                uniform_array_lengths: self.uniform_array_lengths,
+            })
             .init_resource::<SpecializedRenderPipelines<PostProcessPipeline>>()
+            .init_resource::<ExtractedArray<ExtractedPointLight2d>>()
+            .init_resource::<ExtractedArray<ExtractedLightOccluder2d>>()
+            .init_resource::<ExtractedArray<ExtractedFogVolume2d>>()
+            .init_resource::<ExtractedLightBudgets>()
+            .init_resource::<ViewPointLightBuffers>()
+            .init_resource::<Lighting2dBindGroupCache>()
+            .init_resource::<GiCascadeUniforms>()
+            .init_resource::<ExtractedIlluminated2d>()
+            .init_resource::<IlluminationReadbacks>()
             .add_systems(
                 ExtractSchedule,
                 (
-                    extract_lighting_settings,
+                    extract_lighting_settings
+                        .after(extract_light_occluders)
+                        .after(extract_point_lights)
+                        .after(extract_light_budgets)
+                        .after(extract_fog_volumes),
                     extract_light_occluders,
                     extract_point_lights,
+                    extract_light_budgets,
+                    extract_fog_volumes,
+                    extract_temporal_accumulation,
+                    extract_lighting_debug,
+                    extract_illuminated,
                 ),
             )
             .add_systems(
@@ -96,11 +366,30 @@ impl Plugin for Lighting2dPlugin {
                         .after(prepare_view_targets)
                         .in_set(RenderSet::ManageViews),
                     prepare_post_process_pipelines.in_set(RenderSet::Prepare),
+                    (
+                        prepare_lighting_array_buffer::<ExtractedPointLight2d>,
+                        prepare_lighting_array_buffer::<ExtractedLightOccluder2d>,
+                        prepare_lighting_array_buffer::<ExtractedFogVolume2d>,
+                        prepare_view_point_light_buffers,
+                        prepare_gi_cascade_uniforms,
+                        prepare_illumination_readbacks,
+                    )
+                        .in_set(RenderSet::PrepareResources),
                     prepare_lighting_bind_groups.in_set(RenderSet::PrepareBindGroups),
+                    (receive_illumination_readbacks, map_illumination_readbacks)
+                        .chain()
+                        .in_set(RenderSet::Cleanup),
                 ),
             )
             .add_render_graph_node::<ViewNodeRunner<LightingNode>>(Core2d, LightingLabel)
-            .add_render_graph_edges(Core2d, (Node2d::EndMainPass, LightingLabel));
+            .add_render_graph_edges(
+                Core2d,
+                (
+                    self.run_after.clone(),
+                    LightingLabel,
+                    self.run_before.clone(),
+                ),
+            );
     }
 
     fn finish(&self, app: &mut App) {
@@ -108,8 +397,84 @@ impl Plugin for Lighting2dPlugin {
             return;
         };
 
+        let render_device = render_app.world().resource::<RenderDevice>();
+        self.validate_device(render_device);
+
+        let lengths = self.uniform_array_lengths;
+        let point_lights = LightingArrayBuffer::<ExtractedPointLight2d>::new(
+            render_device,
+            "point_lights_buffer",
+            lengths.point_lights,
+        );
+        let light_occluders = LightingArrayBuffer::<ExtractedLightOccluder2d>::new(
+            render_device,
+            "light_occluders_buffer",
+            lengths.occluders,
+        );
+        let fog_volumes = LightingArrayBuffer::<ExtractedFogVolume2d>::new(
+            render_device,
+            "fog_volumes_buffer",
+            lengths.fog_volumes,
+        );
+
         render_app
+            .insert_resource(point_lights)
+            .insert_resource(light_occluders)
+            .insert_resource(fog_volumes)
             .init_resource::<Lighting2dPrepassPipelines>()
-            .init_resource::<PostProcessPipeline>();
+            .init_resource::<PostProcessPipeline>()
+            .init_resource::<BlueNoiseTexture>();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn default_is_valid() {
+        Lighting2dPlugin::default().validate();
+        Lighting2dPlugin {
+            run_after: Node2d::Bloom,
+            run_before: Node2d::Tonemapping,
+            sdf_format: TextureFormat::R16Float,
+            lighting_format: TextureFormat::Rg11b10Float,
+            uniform_array_lengths: UniformArrayLengths {
+                point_lights: 32,
+                ..default()
+            },
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "run_after")]
+    fn runs_after_the_main_pass() {
+        Lighting2dPlugin {
+            run_after: Node2d::StartMainPass,
+            ..default()
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "run_before")]
+    fn runs_before_after_run_after() {
+        Lighting2dPlugin {
+            run_after: Node2d::Tonemapping,
+            run_before: Node2d::Bloom,
+            ..default()
+        }
+        .validate();
+    }
+
+    #[test]
+    #[should_panic(expected = "lighting_format")]
+    fn unsupported_lighting_format() {
+        Lighting2dPlugin {
+            lighting_format: TextureFormat::Rgba8Unorm,
+            ..default()
+        }
+        .validate();
     }
 }
@@ -1,25 +1,333 @@
+use std::{marker::PhantomData, num::NonZeroU64, ops::Range};
+
 use bevy::{
+    core::FrameCount,
+    ecs::entity::{EntityHashMap, EntityHashSet},
     prelude::*,
     render::{
         extract_component::ComponentUniforms,
         render_resource::{
-            BindGroup, BindGroupEntries, CachedRenderPipelineId, GpuArrayBuffer, PipelineCache,
-            SamplerDescriptor, SpecializedRenderPipelines, TextureDescriptor, TextureDimension,
-            TextureFormat, TextureUsages,
+            binding_types::{storage_buffer_read_only_sized, uniform_buffer_sized},
+            encase::{private::WriteInto, StorageBuffer},
+            BindGroup, BindGroupEntries, BindGroupLayoutEntryBuilder, BindingResource, Buffer,
+            BufferBinding, BufferDescriptor, BufferId, BufferUsages, CachedRenderPipelineId,
+            DynamicUniformBuffer, Extent3d, PipelineCache, ShaderSize, ShaderType,
+            SpecializedRenderPipelines, TextureDataOrder, TextureDescriptor, TextureDimension,
+            TextureFormat, TextureUsages, TextureView, TextureViewDescriptor, TextureViewId,
         },
-        renderer::RenderDevice,
+        renderer::{RenderDevice, RenderQueue},
         texture::{CachedTexture, TextureCache},
         view::{ExtractedView, ViewTarget, ViewUniforms},
     },
+    utils::HashMap,
 };
 
 use crate::{
-    extract::{ExtractedLightOccluder2d, ExtractedLighting2dSettings, ExtractedPointLight2d},
-    pipeline::{Lighting2dPipelineKey, Lighting2dPrepassPipelines, PostProcessPipeline},
+    extract::{
+        ExtractedArray, ExtractedFogVolume2d, ExtractedLightBudgets, ExtractedLightOccluder2d,
+        ExtractedLighting2dSettings, ExtractedPointLight2d, ExtractedTemporalAccumulation,
+    },
+    pipeline::{
+        Lighting2dPipelineKey, Lighting2dPrepassPipelines, Lighting2dRenderConfig,
+        PostProcessPipeline,
+    },
+    prelude::{BlurMode, GlobalIllumination, Lighting2dDebug, Lighting2dSettings},
 };
 
+/// Default array length of a batch when storage buffers are not available, see
+/// [`UniformArrayLengths`](crate::prelude::UniformArrayLengths).
+pub const UNIFORM_ARRAY_LEN: usize = 82;
+
+/// An element of a [`LightingArrayBuffer`].
+pub trait LightingArrayItem:
+    ShaderType + ShaderSize + WriteInto + Clone + Send + Sync + 'static
+{
+    /// Value of the unused slots of a uniform batch. It must not contribute to the result.
+    fn padding() -> Self;
+
+    /// Whether the batches past the first one are drawn in extra passes. Otherwise the values
+    /// past the uniform array length are ignored.
+    const BATCHED: bool = true;
+}
+
+/// Persistent GPU copy of an [`ExtractedArray`].
+///
+/// Backed by a storage buffer that grows as needed. On targets without storage buffer support, the
+/// values are split into batches of `array_len` elements in a uniform buffer, each bound
+/// with its own dynamic offset and accumulated by drawing once per batch.
+#[derive(Resource)]
+pub struct LightingArrayBuffer<T> {
+    label: &'static str,
+    buffer: Option<Buffer>,
+    storage: bool,
+    /// Elements of a uniform batch, passed to the shaders as the `MAX_LIGHTS`, `MAX_OCCLUDERS` or
+    /// `MAX_FOG_VOLUMES` shader def
+    array_len: usize,
+    /// Allocated elements for storage buffers, allocated batches for uniform buffers
+    capacity: usize,
+    batch_stride: u64,
+    batches: usize,
+    reported_batches: usize,
+    marker: PhantomData<T>,
+}
+
+impl<T: LightingArrayItem> LightingArrayBuffer<T> {
+    pub fn new(render_device: &RenderDevice, label: &'static str, array_len: usize) -> Self {
+        let limits = render_device.limits();
+        let batch_size = Self::uniform_batch_size(array_len);
+        let alignment = limits.min_uniform_buffer_offset_alignment as u64;
+
+        Self {
+            label,
+            buffer: None,
+            storage: limits.max_storage_buffers_per_shader_stage > 0,
+            array_len,
+            capacity: 0,
+            batch_stride: batch_size.div_ceil(alignment) * alignment,
+            batches: 1,
+            reported_batches: 1,
+            marker: PhantomData,
+        }
+    }
+
+    pub fn binding_layout(
+        render_device: &RenderDevice,
+        array_len: usize,
+    ) -> BindGroupLayoutEntryBuilder {
+        if render_device.limits().max_storage_buffers_per_shader_stage > 0 {
+            storage_buffer_read_only_sized(false, Some(T::min_size()))
+        } else {
+            uniform_buffer_sized(true, NonZeroU64::new(Self::uniform_batch_size(array_len)))
+        }
+    }
+
+    /// Bytes of a uniform batch of `array_len` elements
+    pub fn uniform_batch_size(array_len: usize) -> u64 {
+        T::SHADER_SIZE.get() * array_len as u64
+    }
+
+    /// Elements of a uniform batch
+    pub fn array_len(&self) -> usize {
+        self.array_len
+    }
+
+    pub fn buffer(&self) -> Option<&Buffer> {
+        self.buffer.as_ref()
+    }
+
+    pub fn binding(&self) -> Option<BindingResource<'_>> {
+        let buffer = self.buffer.as_ref()?;
+
+        Some(if self.storage {
+            buffer.as_entire_binding()
+        } else {
+            BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: 0,
+                size: NonZeroU64::new(Self::uniform_batch_size(self.array_len)),
+            })
+        })
+    }
+
+    /// The dynamic offset of each batch to draw, or a single `None` for storage buffers, which
+    /// hold every value and are bound without an offset.
+    pub fn batches(&self) -> impl Iterator<Item = Option<u32>> + '_ {
+        let batches = if self.storage { 1 } else { self.batches };
+
+        (0..batches).map(|batch| (!self.storage).then(|| (batch as u64 * self.batch_stride) as u32))
+    }
+
+    /// Rewrites every slot, for values that are rebuilt every frame.
+    pub fn write_all(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        values: &[T],
+    ) {
+        self.write(render_device, render_queue, values, Some(0..usize::MAX));
+    }
+
+    fn write(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        values: &[T],
+        dirty: Option<Range<usize>>,
+    ) {
+        if self.storage {
+            self.write_storage(render_device, render_queue, values, dirty);
+        } else {
+            self.write_uniform(render_device, render_queue, values, dirty);
+        }
+    }
+
+    fn write_storage(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        values: &[T],
+        dirty: Option<Range<usize>>,
+    ) {
+        let capacity = values.len().max(1).next_power_of_two();
+
+        let range = if self.buffer.is_none() || self.capacity < capacity {
+            self.capacity = capacity;
+            self.buffer = Some(self.create_buffer(
+                render_device,
+                T::SHADER_SIZE.get() * capacity as u64,
+                BufferUsages::STORAGE,
+            ));
+
+            0..values.len()
+        } else if let Some(dirty) = dirty {
+            // Slots past the length are never read, the shaders get the count from the settings
+            dirty.start.min(values.len())..dirty.end.min(values.len())
+        } else {
+            return;
+        };
+
+        if let (Some(buffer), false) = (&self.buffer, range.is_empty()) {
+            let mut bytes = StorageBuffer::new(Vec::new());
+            bytes.write(&values[range.clone()]).unwrap();
+
+            render_queue.write_buffer(
+                buffer,
+                T::SHADER_SIZE.get() * range.start as u64,
+                bytes.as_ref(),
+            );
+        }
+    }
+
+    fn write_uniform(
+        &mut self,
+        render_device: &RenderDevice,
+        render_queue: &RenderQueue,
+        values: &[T],
+        dirty: Option<Range<usize>>,
+    ) {
+        self.batches = values.len().div_ceil(self.array_len).max(1);
+
+        if self.batches > self.reported_batches {
+            if T::BATCHED {
+                warn!(
+                    "{} values in {} exceed the uniform array length of {} supported without \
+                    storage buffers, they will be drawn in {} passes",
+                    values.len(),
+                    self.label,
+                    self.array_len,
+                    self.batches
+                );
+            } else {
+                warn!(
+                    "{} values in {} exceed the uniform array length of {} supported without \
+                    storage buffers, the ones past it are ignored",
+                    values.len(),
+                    self.label,
+                    self.array_len
+                );
+            }
+            self.reported_batches = self.batches;
+        }
+
+        let range = if self.buffer.is_none() || self.capacity < self.batches {
+            self.capacity = self.batches.next_power_of_two();
+            self.buffer = Some(self.create_buffer(
+                render_device,
+                self.batch_stride * self.capacity as u64,
+                BufferUsages::UNIFORM,
+            ));
+
+            0..self.capacity * self.array_len
+        } else if let Some(dirty) = dirty {
+            // Slots vacated since the last upload are overwritten with padding
+            dirty.start..dirty.end.min(self.capacity * self.array_len)
+        } else {
+            return;
+        };
+
+        let Some(buffer) = &self.buffer else {
+            return;
+        };
+
+        let mut start = range.start;
+        while start < range.end {
+            let batch = start / self.array_len;
+            let end = range.end.min((batch + 1) * self.array_len);
+
+            let batch_values = (start..end)
+                .map(|index| values.get(index).cloned().unwrap_or_else(T::padding))
+                .collect::<Vec<_>>();
+
+            let mut bytes = StorageBuffer::new(Vec::new());
+            bytes.write(&batch_values[..]).unwrap();
+
+            render_queue.write_buffer(
+                buffer,
+                self.batch_stride * batch as u64
+                    + T::SHADER_SIZE.get() * (start - batch * self.array_len) as u64,
+                bytes.as_ref(),
+            );
+
+            start = end;
+        }
+    }
+
+    fn create_buffer(
+        &self,
+        render_device: &RenderDevice,
+        size: u64,
+        usage: BufferUsages,
+    ) -> Buffer {
+        render_device.create_buffer(&BufferDescriptor {
+            label: Some(self.label),
+            size,
+            usage: usage | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+pub fn prepare_lighting_array_buffer<T: LightingArrayItem>(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut extracted: ResMut<ExtractedArray<T>>,
+    mut buffer: ResMut<LightingArrayBuffer<T>>,
+) {
+    let dirty = extracted.take_dirty();
+    buffer.write(&render_device, &render_queue, extracted.values(), dirty);
+}
+
+/// Point light buffers of the views with a [`LightBudget`](crate::prelude::LightBudget), keyed by
+/// view entity.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct ViewPointLightBuffers(EntityHashMap<LightingArrayBuffer<ExtractedPointLight2d>>);
+
+pub fn prepare_view_point_light_buffers(
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    config: Res<Lighting2dRenderConfig>,
+    light_budgets: Res<ExtractedLightBudgets>,
+    mut view_point_lights: ResMut<ViewPointLightBuffers>,
+) {
+    view_point_lights.retain(|view, _| light_budgets.contains_key(view));
+
+    for (view, point_lights) in light_budgets.iter() {
+        view_point_lights
+            .entry(*view)
+            .or_insert_with(|| {
+                LightingArrayBuffer::new(
+                    &render_device,
+                    "view_point_lights_buffer",
+                    config.uniform_array_lengths.point_lights,
+                )
+            })
+            .write_all(&render_device, &render_queue, point_lights);
+    }
+}
+
 fn create_aux_texture(
-    view_target: &ViewTarget,
+    size: Extent3d,
+    format: TextureFormat,
     texture_cache: &mut TextureCache,
     render_device: &RenderDevice,
     label: &'static str,
@@ -28,49 +336,368 @@ fn create_aux_texture(
         render_device,
         TextureDescriptor {
             label: Some(label),
-            size: view_target.main_texture().size(),
+            size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
-            format: TextureFormat::Rgba16Float,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            format,
+            // Copied from by the `Illuminated2d` readback, and to the temporal history until the
+            // temporal pipeline is compiled
+            usage: TextureUsages::RENDER_ATTACHMENT
+                | TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_SRC
+                | TextureUsages::COPY_DST,
             view_formats: &[],
         },
     )
 }
 
+/// Number of downsamples of a dual Kawase blur. Each one roughly doubles the blur size, and the
+/// chain stops before the smallest texture gets under a pixel.
+fn dual_kawase_levels(coc: f32, size: UVec2) -> u32 {
+    let sigma = coc * 0.25;
+    let max_levels = size.min_element().max(1).ilog2().max(1);
+
+    (sigma.max(1.0).log2().ceil() as u32).clamp(1, max_levels)
+}
+
+/// Screen space blue noise used to jitter the raymarch
+#[derive(Resource)]
+pub struct BlueNoiseTexture(pub TextureView);
+
+impl FromWorld for BlueNoiseTexture {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let render_queue = world.resource::<RenderQueue>();
+
+        // 64x64 single channel blue noise, generated with the void and cluster method
+        let texture = render_device.create_texture_with_data(
+            render_queue,
+            &TextureDescriptor {
+                label: Some("blue_noise_texture"),
+                size: Extent3d {
+                    width: 64,
+                    height: 64,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::R8Unorm,
+                usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            TextureDataOrder::LayerMajor,
+            include_bytes!("textures/blue_noise.bin"),
+        );
+
+        Self(texture.create_view(&TextureViewDescriptor::default()))
+    }
+}
+
+pub use gi_cascade_uniform::GiCascadeUniform;
+
+// Scoped like the uniforms of `extract.rs`, the field checks of the `ShaderType` derive are
+// reported as dead code
+#[allow(dead_code)]
+mod gi_cascade_uniform {
+    use bevy::{math::Vec2, render::render_resource::ShaderType};
+
+    /// A cascade of [`GlobalIllumination`](crate::prelude::GlobalIllumination), see `gi.wgsl`
+    #[derive(ShaderType)]
+    pub struct GiCascadeUniform {
+        pub cascade: u32,
+        pub cascade_count: u32,
+        pub probe_spacing: f32,
+        pub interval: f32,
+        pub max_steps: u32,
+        pub intensity: f32,
+        pub size: Vec2,
+    }
+}
+
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct GiCascadeUniforms(DynamicUniformBuffer<GiCascadeUniform>);
+
+/// Offsets of the cascades of a view in [`GiCascadeUniforms`], from the first to the last
+#[derive(Component, Deref)]
+pub struct ViewGiCascadeOffsets(Vec<u32>);
+
+pub fn prepare_gi_cascade_uniforms(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    render_queue: Res<RenderQueue>,
+    mut gi_cascades: ResMut<GiCascadeUniforms>,
+    views_query: Query<(Entity, &ViewTarget, &Lighting2dSettings)>,
+) {
+    gi_cascades.clear();
+
+    for (entity, view_target, settings) in &views_query {
+        let Some(gi) = &settings.global_illumination else {
+            continue;
+        };
+
+        let size = view_target.main_texture().size();
+        let cascade_count = gi.cascade_count.max(1);
+
+        let offsets = (0..cascade_count)
+            .map(|cascade| {
+                gi_cascades.push(&GiCascadeUniform {
+                    cascade,
+                    cascade_count,
+                    probe_spacing: gi.probe_spacing.max(1) as f32,
+                    interval: gi.interval,
+                    max_steps: gi.max_steps,
+                    intensity: gi.intensity,
+                    size: Vec2::new(size.width as f32, size.height as f32),
+                })
+            })
+            .collect();
+
+        commands
+            .entity(entity)
+            .insert(ViewGiCascadeOffsets(offsets));
+    }
+
+    gi_cascades.write_buffer(&render_device, &render_queue);
+}
+
+/// Size of the texture holding any cascade. Probes get twice as far apart with each cascade while
+/// the texels of each probe double on both axes, so it's about the same size for every cascade,
+/// the last one being the largest because of rounding.
+fn gi_cascades_size(size: Extent3d, gi: &GlobalIllumination) -> Extent3d {
+    let last_cascade = gi.cascade_count.max(1) - 1;
+    let probe_spacing = gi.probe_spacing.max(1) << last_cascade;
+    let probe_side = 2 << last_cascade;
+
+    Extent3d {
+        width: size.width.div_ceil(probe_spacing) * probe_side,
+        height: size.height.div_ceil(probe_spacing) * probe_side,
+        depth_or_array_layers: 1,
+    }
+}
+
+/// Temporal accumulation targets, swapped every frame
+pub struct Lighting2dHistoryTextures {
+    /// Accumulated lighting of the previous frame
+    pub read: CachedTexture,
+    /// Accumulated lighting of the current frame
+    pub write: CachedTexture,
+}
+
+/// Global illumination targets
+pub struct Lighting2dGiTextures {
+    pub albedo: CachedTexture,
+    pub emissive: CachedTexture,
+    /// Cascades are traced from the last to the first, each one writing to one of these textures
+    /// and reading the next cascade from the other
+    pub cascades: [CachedTexture; 2],
+}
+
 #[derive(Component)]
 pub struct Lighting2dAuxiliaryTextures {
     pub sdf: CachedTexture,
     pub lighting: CachedTexture,
+    pub scattering: CachedTexture,
+    pub gi: Option<Lighting2dGiTextures>,
+    pub history: Option<Lighting2dHistoryTextures>,
     pub blur: Option<CachedTexture>,
+    /// Intermediate blur targets. The horizontal pass output for [`BlurMode::Gaussian`] and
+    /// [`BlurMode::Bilateral`], and the downsample chain for [`BlurMode::DualKawase`]
+    pub blur_chain: Vec<CachedTexture>,
+    /// Raymarch steps and light count, for the [`Lighting2dDebug`] views that show them
+    pub debug_stats: Option<CachedTexture>,
+}
+
+impl Lighting2dAuxiliaryTextures {
+    /// The lighting after temporal accumulation, if enabled
+    pub fn accumulated_lighting(&self) -> &CachedTexture {
+        self.history
+            .as_ref()
+            .map_or(&self.lighting, |history| &history.write)
+    }
+
+    /// The lighting after the blur, if any, multiplying the scene in the post process
+    pub fn final_lighting(&self) -> &CachedTexture {
+        self.blur
+            .as_ref()
+            .unwrap_or_else(|| self.accumulated_lighting())
+    }
 }
 
 pub fn prepare_lighting_auxiliary_textures(
     mut commands: Commands,
     render_device: Res<RenderDevice>,
     mut texture_cache: ResMut<TextureCache>,
-    view_targets: Query<(Entity, &ViewTarget, &ExtractedLighting2dSettings)>,
+    frame_count: Res<FrameCount>,
+    config: Res<Lighting2dRenderConfig>,
+    view_targets: Query<(
+        Entity,
+        &ViewTarget,
+        &Lighting2dSettings,
+        &ExtractedLighting2dSettings,
+        Has<ExtractedTemporalAccumulation>,
+        Option<&Lighting2dDebug>,
+    )>,
 ) {
-    for (entity, view_target, settings) in &view_targets {
+    for (entity, view_target, settings, extracted_settings, temporal_accumulation, debug) in
+        &view_targets
+    {
+        let size = view_target.main_texture().size();
+
+        let gi = settings.global_illumination.as_ref().map(|gi| {
+            let cascades_size = gi_cascades_size(size, gi);
+
+            Lighting2dGiTextures {
+                albedo: create_aux_texture(
+                    size,
+                    config.lighting_format,
+                    &mut texture_cache,
+                    &render_device,
+                    "gi_albedo",
+                ),
+                emissive: create_aux_texture(
+                    size,
+                    config.lighting_format,
+                    &mut texture_cache,
+                    &render_device,
+                    "gi_emissive",
+                ),
+                cascades: [
+                    create_aux_texture(
+                        cascades_size,
+                        config.lighting_format,
+                        &mut texture_cache,
+                        &render_device,
+                        "gi_cascades_1",
+                    ),
+                    create_aux_texture(
+                        cascades_size,
+                        config.lighting_format,
+                        &mut texture_cache,
+                        &render_device,
+                        "gi_cascades_2",
+                    ),
+                ],
+            }
+        });
+
+        let history = temporal_accumulation.then(|| {
+            let history_1 = create_aux_texture(
+                size,
+                config.lighting_format,
+                &mut texture_cache,
+                &render_device,
+                "lighting_history_1",
+            );
+            let history_2 = create_aux_texture(
+                size,
+                config.lighting_format,
+                &mut texture_cache,
+                &render_device,
+                "lighting_history_2",
+            );
+
+            if frame_count.0 % 2 == 0 {
+                Lighting2dHistoryTextures {
+                    read: history_1,
+                    write: history_2,
+                }
+            } else {
+                Lighting2dHistoryTextures {
+                    read: history_2,
+                    write: history_1,
+                }
+            }
+        });
+
+        let (blur, blur_chain) = if extracted_settings.blur > 0.0 {
+            let blur_chain = match settings.blur_mode {
+                BlurMode::Gaussian | BlurMode::Bilateral => vec![create_aux_texture(
+                    size,
+                    config.lighting_format,
+                    &mut texture_cache,
+                    &render_device,
+                    "blur_intermediate",
+                )],
+                BlurMode::DualKawase => {
+                    let screen_size = UVec2::new(size.width, size.height);
+                    let mut coc = extracted_settings.blur;
+
+                    // Same scaling as the blur shader
+                    if extracted_settings.fixed_resolution == 0 {
+                        coc *= screen_size.element_sum() as f32 / 2000.0;
+                    }
+
+                    (1..=dual_kawase_levels(coc, screen_size))
+                        .map(|level| {
+                            let level_size = (screen_size >> level).max(UVec2::ONE);
+
+                            create_aux_texture(
+                                Extent3d {
+                                    width: level_size.x,
+                                    height: level_size.y,
+                                    depth_or_array_layers: 1,
+                                },
+                                config.lighting_format,
+                                &mut texture_cache,
+                                &render_device,
+                                "blur_downsample",
+                            )
+                        })
+                        .collect()
+                }
+            };
+
+            (
+                Some(create_aux_texture(
+                    size,
+                    config.lighting_format,
+                    &mut texture_cache,
+                    &render_device,
+                    "blur",
+                )),
+                blur_chain,
+            )
+        } else {
+            (None, Vec::new())
+        };
+
         commands.entity(entity).insert(Lighting2dAuxiliaryTextures {
-            sdf: create_aux_texture(view_target, &mut texture_cache, &render_device, "sdf"),
+            sdf: create_aux_texture(
+                size,
+                config.sdf_format,
+                &mut texture_cache,
+                &render_device,
+                "sdf",
+            ),
             lighting: create_aux_texture(
-                view_target,
+                size,
+                config.lighting_format,
                 &mut texture_cache,
                 &render_device,
                 "lighting",
             ),
-            blur: if settings.blur > 0.0 {
-                Some(create_aux_texture(
-                    view_target,
+            scattering: create_aux_texture(
+                size,
+                config.lighting_format,
+                &mut texture_cache,
+                &render_device,
+                "scattering",
+            ),
+            gi,
+            history,
+            blur,
+            blur_chain,
+            debug_stats: debug.filter(|debug| debug.needs_stats()).map(|_| {
+                create_aux_texture(
+                    size,
+                    config.lighting_format,
                     &mut texture_cache,
                     &render_device,
-                    "blur",
-                ))
-            } else {
-                None
-            },
+                    "debug_stats",
+                )
+            }),
         });
     }
 }
@@ -83,77 +710,520 @@ pub fn prepare_post_process_pipelines(
     pipeline_cache: Res<PipelineCache>,
     mut post_process_pipelines: ResMut<SpecializedRenderPipelines<PostProcessPipeline>>,
     post_process_pipeline: Res<PostProcessPipeline>,
-    views_query: Query<(Entity, &ExtractedView), With<ExtractedLighting2dSettings>>,
+    views_query: Query<
+        (Entity, &ExtractedView, Option<&Lighting2dDebug>),
+        With<ExtractedLighting2dSettings>,
+    >,
 ) {
-    for (entity, view) in &views_query {
+    for (entity, view, debug) in &views_query {
         commands
             .entity(entity)
             .insert(Lighting2dPostProcessPipelineId(
                 post_process_pipelines.specialize(
                     &pipeline_cache,
                     &post_process_pipeline,
-                    Lighting2dPipelineKey { hdr: view.hdr },
+                    Lighting2dPipelineKey {
+                        hdr: view.hdr,
+                        debug: debug.copied(),
+                    },
                 ),
             ));
     }
 }
 
+/// A fullscreen blur pass reading the texture bound in `bind_group` and writing to `target`
+pub struct BlurPass {
+    pub pipeline: CachedRenderPipelineId,
+    pub bind_group: BindGroup,
+    pub target: TextureView,
+}
+
+pub struct GiBindGroups {
+    /// Traces a cascade reading the next one from the matching cascade texture
+    pub trace: [BindGroup; 2],
+    /// Adds the first cascade to the lighting
+    pub apply: BindGroup,
+}
+
 #[derive(Component)]
 pub struct Lighting2dSurfaceBindGroups {
     pub sdf: BindGroup,
     pub lighting: BindGroup,
-    pub blur: BindGroup,
+    /// Ambient occlusion pass, if enabled
+    pub ambient_occlusion: Option<BindGroup>,
+    /// Global illumination passes, if enabled
+    pub gi: Option<GiBindGroups>,
+    /// Temporal accumulation pass, if enabled
+    pub temporal: Option<BindGroup>,
+    /// Blur passes in the order they run, empty when the blur is disabled
+    pub blur: Vec<BlurPass>,
+    /// Post process reading each of the two main textures of the view, the one holding the scene
+    /// is only known when the render graph runs
+    pub post_process: [(TextureViewId, BindGroup); 2],
+}
+
+/// A buffer or texture view bound in a [`Lighting2dBindGroupCache`] entry
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BindingId {
+    Buffer(BufferId),
+    TextureView(TextureViewId),
+}
+
+impl From<&Buffer> for BindingId {
+    fn from(buffer: &Buffer) -> Self {
+        Self::Buffer(buffer.id())
+    }
+}
+
+impl From<&TextureView> for BindingId {
+    fn from(texture_view: &TextureView) -> Self {
+        Self::TextureView(texture_view.id())
+    }
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct BindGroupKey {
+    label: &'static str,
+    bindings: Vec<BindingId>,
+}
+
+/// Bind groups of each view, reused across frames while the buffers and texture views they bind
+/// stay the same. Buffers are reallocated when they grow and the texture cache hands out new
+/// textures when a view is resized, which changes the keys.
+#[derive(Resource, Default)]
+pub struct Lighting2dBindGroupCache {
+    frame: u32,
+    views: EntityHashMap<HashMap<BindGroupKey, (BindGroup, u32)>>,
+}
+
+impl Lighting2dBindGroupCache {
+    /// The bind group of `view` binding `bindings`, created if there's none yet
+    fn get_or_create<const N: usize>(
+        &mut self,
+        view: Entity,
+        label: &'static str,
+        bindings: [BindingId; N],
+        create: impl FnOnce() -> BindGroup,
+    ) -> BindGroup {
+        let frame = self.frame;
+        let (bind_group, last_used) = self
+            .views
+            .entry(view)
+            .or_default()
+            .entry(BindGroupKey {
+                label,
+                bindings: bindings.to_vec(),
+            })
+            .or_insert_with(|| (create(), frame));
+
+        *last_used = frame;
+        bind_group.clone()
+    }
+
+    /// Drops the bind groups of the views that are gone and the ones unused since the last frame.
+    /// Those are kept because the temporal accumulation alternates between two history textures.
+    fn evict(&mut self, views: &EntityHashSet) {
+        let frame = self.frame;
+
+        self.views.retain(|view, bind_groups| {
+            bind_groups.retain(|_, (_, last_used)| frame.wrapping_sub(*last_used) <= 1);
+            views.contains(view)
+        });
+    }
 }
 
 pub fn prepare_lighting_bind_groups(
     mut commands: Commands,
     prepass_pipelines: Res<Lighting2dPrepassPipelines>,
+    post_process_pipeline: Res<PostProcessPipeline>,
     render_device: Res<RenderDevice>,
+    mut cache: ResMut<Lighting2dBindGroupCache>,
     view_uniforms: Res<ViewUniforms>,
     light_settings: Res<ComponentUniforms<ExtractedLighting2dSettings>>,
-    point_lights: Res<GpuArrayBuffer<ExtractedPointLight2d>>,
-    light_occluders: Res<GpuArrayBuffer<ExtractedLightOccluder2d>>,
-    views_query: Query<(Entity, &Lighting2dAuxiliaryTextures), With<ExtractedLighting2dSettings>>,
+    temporal_uniforms: Res<ComponentUniforms<ExtractedTemporalAccumulation>>,
+    gi_cascades: Res<GiCascadeUniforms>,
+    point_light_buffer: Res<LightingArrayBuffer<ExtractedPointLight2d>>,
+    view_point_lights: Res<ViewPointLightBuffers>,
+    light_occluder_buffer: Res<LightingArrayBuffer<ExtractedLightOccluder2d>>,
+    fog_volume_buffer: Res<LightingArrayBuffer<ExtractedFogVolume2d>>,
+    blue_noise: Res<BlueNoiseTexture>,
+    views_query: Query<
+        (
+            Entity,
+            &ViewTarget,
+            &Lighting2dSettings,
+            &Lighting2dAuxiliaryTextures,
+        ),
+        With<ExtractedLighting2dSettings>,
+    >,
 ) {
-    let (Some(view_uniform), Some(lighting_settings), Some(light_occluders), Some(point_lights)) = (
+    let (
+        Some(view_uniform),
+        Some(lighting_settings),
+        Some(light_occluders),
+        Some(point_lights),
+        Some(fog_volumes),
+    ) = (
         view_uniforms.uniforms.binding(),
         light_settings.binding(),
-        light_occluders.binding(),
-        point_lights.binding(),
-    ) else {
+        light_occluder_buffer.binding(),
+        point_light_buffer.binding(),
+        fog_volume_buffer.binding(),
+    )
+    else {
         return;
     };
 
-    let sampler = render_device.create_sampler(&SamplerDescriptor::default());
+    // Set whenever the bindings above are
+    let view_uniform_id = BindingId::from(view_uniforms.uniforms.buffer().unwrap());
+    let lighting_settings_id = BindingId::from(light_settings.uniforms().buffer().unwrap());
+    let light_occluders_id = BindingId::from(light_occluder_buffer.buffer().unwrap());
+    let fog_volumes_id = BindingId::from(fog_volume_buffer.buffer().unwrap());
 
-    for (entity, aux_textures) in &views_query {
-        commands.entity(entity).insert(Lighting2dSurfaceBindGroups {
-            sdf: render_device.create_bind_group(
-                "sdf_bind_group",
-                &prepass_pipelines.sdf_layout,
-                &BindGroupEntries::sequential((view_uniform.clone(), light_occluders.clone())),
-            ),
-            lighting: render_device.create_bind_group(
-                "lighting2d_bind_group",
-                &prepass_pipelines.lighting_layout,
-                &BindGroupEntries::sequential((
-                    view_uniform.clone(),
-                    lighting_settings.clone(),
-                    point_lights.clone(),
-                    &aux_textures.sdf.default_view,
-                    &sampler,
-                )),
-            ),
-            blur: render_device.create_bind_group(
-                "blur_bind_group",
-                &prepass_pipelines.blur_layout,
-                &BindGroupEntries::sequential((
-                    view_uniform.clone(),
-                    lighting_settings.clone(),
-                    &aux_textures.lighting.default_view,
-                    &sampler,
-                )),
+    let temporal_uniform = temporal_uniforms
+        .uniforms()
+        .buffer()
+        .zip(temporal_uniforms.binding());
+    let gi_cascade = gi_cascades.buffer().zip(gi_cascades.binding());
+    let sampler = &prepass_pipelines.sampler;
+
+    cache.frame = cache.frame.wrapping_add(1);
+
+    let mut views = EntityHashSet::default();
+
+    for (entity, view_target, settings, aux_textures) in &views_query {
+        let (point_lights_id, point_lights) = match view_point_lights.get(&entity) {
+            Some(view_point_lights) => {
+                match view_point_lights.buffer().zip(view_point_lights.binding()) {
+                    Some((buffer, binding)) => (BindingId::from(buffer), binding),
+                    None => continue,
+                }
+            }
+            None => (
+                BindingId::from(point_light_buffer.buffer().unwrap()),
+                point_lights.clone(),
             ),
+        };
+
+        views.insert(entity);
+
+        let sdf = cache.get_or_create(
+            entity,
+            "sdf_bind_group",
+            [view_uniform_id, lighting_settings_id, light_occluders_id],
+            || {
+                render_device.create_bind_group(
+                    "sdf_bind_group",
+                    &prepass_pipelines.sdf_layout,
+                    &BindGroupEntries::sequential((
+                        view_uniform.clone(),
+                        lighting_settings.clone(),
+                        light_occluders.clone(),
+                    )),
+                )
+            },
+        );
+
+        let lighting = cache.get_or_create(
+            entity,
+            "lighting2d_bind_group",
+            [
+                view_uniform_id,
+                lighting_settings_id,
+                point_lights_id,
+                (&aux_textures.sdf.default_view).into(),
+                (&blue_noise.0).into(),
+                fog_volumes_id,
+            ],
+            || {
+                render_device.create_bind_group(
+                    "lighting2d_bind_group",
+                    &prepass_pipelines.lighting_layout,
+                    &BindGroupEntries::sequential((
+                        view_uniform.clone(),
+                        lighting_settings.clone(),
+                        point_lights.clone(),
+                        &aux_textures.sdf.default_view,
+                        sampler,
+                        &blue_noise.0,
+                        fog_volumes.clone(),
+                    )),
+                )
+            },
+        );
+
+        let ambient_occlusion = settings.ambient_occlusion.as_ref().map(|_| {
+            cache.get_or_create(
+                entity,
+                "ambient_occlusion_bind_group",
+                [
+                    view_uniform_id,
+                    lighting_settings_id,
+                    (&aux_textures.sdf.default_view).into(),
+                ],
+                || {
+                    render_device.create_bind_group(
+                        "ambient_occlusion_bind_group",
+                        &prepass_pipelines.ambient_occlusion_layout,
+                        &BindGroupEntries::sequential((
+                            view_uniform.clone(),
+                            lighting_settings.clone(),
+                            &aux_textures.sdf.default_view,
+                            sampler,
+                        )),
+                    )
+                },
+            )
+        });
+
+        let gi = aux_textures.gi.as_ref().zip(gi_cascade.as_ref()).map(
+            |(gi, (gi_cascade_buffer, gi_cascade))| {
+                let mut trace = |cascade: &CachedTexture| {
+                    cache.get_or_create(
+                        entity,
+                        "gi_trace_bind_group",
+                        [
+                            view_uniform_id,
+                            lighting_settings_id,
+                            (*gi_cascade_buffer).into(),
+                            (&cascade.default_view).into(),
+                            (&aux_textures.sdf.default_view).into(),
+                            (&aux_textures.lighting.default_view).into(),
+                            (&gi.albedo.default_view).into(),
+                            (&gi.emissive.default_view).into(),
+                        ],
+                        || {
+                            render_device.create_bind_group(
+                                "gi_trace_bind_group",
+                                &prepass_pipelines.gi_trace_layout,
+                                &BindGroupEntries::sequential((
+                                    view_uniform.clone(),
+                                    lighting_settings.clone(),
+                                    gi_cascade.clone(),
+                                    sampler,
+                                    &cascade.default_view,
+                                    &aux_textures.sdf.default_view,
+                                    &aux_textures.lighting.default_view,
+                                    &gi.albedo.default_view,
+                                    &gi.emissive.default_view,
+                                )),
+                            )
+                        },
+                    )
+                };
+
+                let trace = [trace(&gi.cascades[0]), trace(&gi.cascades[1])];
+
+                GiBindGroups {
+                    trace,
+                    apply: cache.get_or_create(
+                        entity,
+                        "gi_apply_bind_group",
+                        [
+                            view_uniform_id,
+                            lighting_settings_id,
+                            (*gi_cascade_buffer).into(),
+                            (&gi.cascades[0].default_view).into(),
+                        ],
+                        || {
+                            render_device.create_bind_group(
+                                "gi_apply_bind_group",
+                                &prepass_pipelines.gi_apply_layout,
+                                &BindGroupEntries::sequential((
+                                    view_uniform.clone(),
+                                    lighting_settings.clone(),
+                                    gi_cascade.clone(),
+                                    sampler,
+                                    &gi.cascades[0].default_view,
+                                )),
+                            )
+                        },
+                    ),
+                }
+            },
+        );
+
+        let temporal = aux_textures
+            .history
+            .as_ref()
+            .zip(temporal_uniform.as_ref())
+            .map(|(history, (temporal_buffer, temporal_uniform))| {
+                cache.get_or_create(
+                    entity,
+                    "temporal_bind_group",
+                    [
+                        view_uniform_id,
+                        (*temporal_buffer).into(),
+                        (&aux_textures.lighting.default_view).into(),
+                        (&history.read.default_view).into(),
+                    ],
+                    || {
+                        render_device.create_bind_group(
+                            "temporal_bind_group",
+                            &prepass_pipelines.temporal_layout,
+                            &BindGroupEntries::sequential((
+                                view_uniform.clone(),
+                                temporal_uniform.clone(),
+                                &aux_textures.lighting.default_view,
+                                &history.read.default_view,
+                                sampler,
+                            )),
+                        )
+                    },
+                )
+            });
+
+        let blur = blur_passes(settings.blur_mode, aux_textures, &prepass_pipelines)
+            .into_iter()
+            .map(|(pipeline, source, target)| BlurPass {
+                pipeline,
+                bind_group: cache.get_or_create(
+                    entity,
+                    "blur_bind_group",
+                    [
+                        view_uniform_id,
+                        lighting_settings_id,
+                        source.into(),
+                        (&aux_textures.sdf.default_view).into(),
+                    ],
+                    || {
+                        render_device.create_bind_group(
+                            "blur_bind_group",
+                            &prepass_pipelines.blur_layout,
+                            &BindGroupEntries::sequential((
+                                view_uniform.clone(),
+                                lighting_settings.clone(),
+                                source,
+                                sampler,
+                                &aux_textures.sdf.default_view,
+                            )),
+                        )
+                    },
+                ),
+                target: target.clone(),
+            })
+            .collect();
+
+        // Any texture will do when the stats are not recorded, they're not read
+        let stats = aux_textures
+            .debug_stats
+            .as_ref()
+            .unwrap_or(&aux_textures.lighting);
+
+        let mut post_process = |source: &TextureView| {
+            let bind_group = cache.get_or_create(
+                entity,
+                "post_process_bind_group",
+                [
+                    source.into(),
+                    (&aux_textures.final_lighting().default_view).into(),
+                    (&aux_textures.scattering.default_view).into(),
+                    (&aux_textures.sdf.default_view).into(),
+                    (&aux_textures.accumulated_lighting().default_view).into(),
+                    (&stats.default_view).into(),
+                ],
+                || {
+                    render_device.create_bind_group(
+                        "post_process_bind_group",
+                        &post_process_pipeline.layout,
+                        &BindGroupEntries::sequential((
+                            source,
+                            &aux_textures.final_lighting().default_view,
+                            &aux_textures.scattering.default_view,
+                            &post_process_pipeline.sampler,
+                            &aux_textures.sdf.default_view,
+                            &aux_textures.accumulated_lighting().default_view,
+                            &stats.default_view,
+                        )),
+                    )
+                },
+            );
+
+            (source.id(), bind_group)
+        };
+
+        let post_process = [
+            post_process(view_target.main_texture_view()),
+            post_process(view_target.main_texture_other_view()),
+        ];
+
+        commands.entity(entity).insert(Lighting2dSurfaceBindGroups {
+            sdf,
+            lighting,
+            ambient_occlusion,
+            gi,
+            temporal,
+            blur,
+            post_process,
         });
     }
+
+    cache.evict(&views);
+}
+
+/// Pipeline, source and target of each blur pass
+fn blur_passes<'a>(
+    blur_mode: BlurMode,
+    aux_textures: &'a Lighting2dAuxiliaryTextures,
+    prepass_pipelines: &Lighting2dPrepassPipelines,
+) -> Vec<(CachedRenderPipelineId, &'a TextureView, &'a TextureView)> {
+    let Some(blur) = &aux_textures.blur else {
+        return Vec::new();
+    };
+
+    let chain = &aux_textures.blur_chain;
+
+    match blur_mode {
+        BlurMode::Gaussian | BlurMode::Bilateral => {
+            let (horizontal_pipeline, vertical_pipeline) = match blur_mode {
+                BlurMode::Bilateral => (
+                    prepass_pipelines.bilateral_horizontal_pipeline,
+                    prepass_pipelines.bilateral_vertical_pipeline,
+                ),
+                _ => (
+                    prepass_pipelines.gaussian_horizontal_pipeline,
+                    prepass_pipelines.gaussian_vertical_pipeline,
+                ),
+            };
+
+            vec![
+                (
+                    horizontal_pipeline,
+                    &aux_textures.accumulated_lighting().default_view,
+                    &chain[0].default_view,
+                ),
+                (
+                    vertical_pipeline,
+                    &chain[0].default_view,
+                    &blur.default_view,
+                ),
+            ]
+        }
+        BlurMode::DualKawase => {
+            let sources = std::iter::once(aux_textures.accumulated_lighting()).chain(chain);
+            let downsamples = sources.zip(chain).map(|(source, target)| {
+                (
+                    prepass_pipelines.kawase_downsample_pipeline,
+                    &source.default_view,
+                    &target.default_view,
+                )
+            });
+
+            // Back up the chain, the first level is upsampled into the blur texture
+            let upsamples = (0..chain.len()).rev().map(|level| {
+                let target = match level {
+                    0 => blur,
+                    _ => &chain[level - 1],
+                };
+
+                (
+                    prepass_pipelines.kawase_upsample_pipeline,
+                    &chain[level].default_view,
+                    &target.default_view,
+                )
+            });
+
+            downsamples.chain(upsamples).collect()
+        }
+    }
 }
@@ -0,0 +1,256 @@
+use std::fmt;
+
+use bevy::{
+    asset::{io::Reader, ron, AssetLoader, AsyncReadExt, LoadContext},
+    prelude::*,
+    reflect::{serde::TypedReflectDeserializer, TypeRegistryArc},
+    utils::HashMap,
+};
+
+use crate::prelude::{AmbientLight2d, DayNightCycle2d, Lighting2dSettings, PointLight2d};
+
+/// Lighting of a level, loaded from a `.lighting.ron` file so it can be tweaked without
+/// recompiling. Every field is optional in the file and defaults to the component defaults.
+///
+/// Inserting a `Handle<LightingProfile2d>` on a camera applies the profile's settings and ambient
+/// light to it once loaded, and again whenever the file is hot reloaded. The ambient light is
+/// skipped on cameras with a [`DayNightCycle2d`], which drives it instead.
+///
+/// ```ron
+/// (
+///     settings: (
+///         blur: 8.0,
+///         raymarch: (max_steps: 48),
+///     ),
+///     ambient_light: (brightness: 0.2),
+///     point_lights: {
+///         "torch": (color: Srgba((red: 1.0, green: 0.6, blue: 0.2, alpha: 1.0)), radius: 120.0),
+///     },
+/// )
+/// ```
+#[derive(Asset, Clone, Default, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct LightingProfile2d {
+    /// Lighting settings of the camera
+    pub settings: Lighting2dSettings,
+    /// Ambient light of the camera, unless it has a [`DayNightCycle2d`]
+    pub ambient_light: AmbientLight2d,
+    /// Named point light templates, see [`LightingProfile2d::point_light`]
+    pub point_lights: HashMap<String, PointLight2d>,
+}
+
+impl LightingProfile2d {
+    /// The point light template called `name`, if any.
+    pub fn point_light(&self, name: &str) -> Option<PointLight2d> {
+        self.point_lights.get(name).cloned()
+    }
+}
+
+/// Loads [`LightingProfile2d`] assets from `.lighting.ron` files.
+pub struct LightingProfile2dLoader {
+    type_registry: TypeRegistryArc,
+}
+
+impl FromWorld for LightingProfile2dLoader {
+    fn from_world(world: &mut World) -> Self {
+        Self {
+            type_registry: world.resource::<AppTypeRegistry>().0.clone(),
+        }
+    }
+}
+
+/// Errors loading a [`LightingProfile2d`].
+#[derive(Debug)]
+pub enum LightingProfile2dLoaderError {
+    /// The file couldn't be read
+    Io(std::io::Error),
+    /// The file isn't a valid profile
+    Ron(ron::error::SpannedError),
+}
+
+impl fmt::Display for LightingProfile2dLoaderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Io(error) => write!(f, "could not read the lighting profile: {error}"),
+            Self::Ron(error) => write!(f, "could not parse the lighting profile: {error}"),
+        }
+    }
+}
+
+impl std::error::Error for LightingProfile2dLoaderError {}
+
+impl AssetLoader for LightingProfile2dLoader {
+    type Asset = LightingProfile2d;
+    type Settings = ();
+    type Error = LightingProfile2dLoaderError;
+
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut Reader<'_>,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<LightingProfile2d, Self::Error> {
+        let mut bytes = Vec::new();
+        reader
+            .read_to_end(&mut bytes)
+            .await
+            .map_err(LightingProfile2dLoaderError::Io)?;
+
+        deserialize_profile(&bytes, &self.type_registry)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["lighting.ron"]
+    }
+}
+
+// Deserialized through reflection, which fills the fields missing from the file with defaults
+fn deserialize_profile(
+    bytes: &[u8],
+    type_registry: &TypeRegistryArc,
+) -> Result<LightingProfile2d, LightingProfile2dLoaderError> {
+    let type_registry = type_registry.read();
+    let registration = type_registry
+        .get(std::any::TypeId::of::<LightingProfile2d>())
+        .expect("`LightingProfile2d` is registered by the `Lighting2dPlugin`");
+
+    let profile = ron::Options::default()
+        .from_bytes_seed(
+            bytes,
+            TypedReflectDeserializer::new(registration, &type_registry),
+        )
+        .map_err(LightingProfile2dLoaderError::Ron)?;
+
+    Ok(LightingProfile2d::from_reflect(&*profile)
+        .expect("deserialized profiles are complete or filled with defaults"))
+}
+
+pub fn apply_lighting_profiles(
+    mut commands: Commands,
+    mut asset_events: EventReader<AssetEvent<LightingProfile2d>>,
+    profiles: Res<Assets<LightingProfile2d>>,
+    cameras_query: Query<(Entity, Ref<Handle<LightingProfile2d>>, Has<DayNightCycle2d>)>,
+) {
+    let reloaded: Vec<AssetId<LightingProfile2d>> = asset_events
+        .read()
+        .filter_map(|event| match event {
+            AssetEvent::LoadedWithDependencies { id } | AssetEvent::Modified { id } => Some(*id),
+            _ => None,
+        })
+        .collect();
+
+    for (entity, handle, has_day_night_cycle) in &cameras_query {
+        if !handle.is_changed() && !reloaded.contains(&handle.id()) {
+            continue;
+        }
+
+        // Applied by the load event if it's not loaded yet
+        let Some(profile) = profiles.get(&*handle) else {
+            continue;
+        };
+
+        let mut entity = commands.entity(entity);
+        entity.insert(profile.settings.clone());
+
+        // The cycle would overwrite it on its next update anyway
+        if !has_day_night_cycle {
+            entity.insert(profile.ambient_light.clone());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn type_registry() -> TypeRegistryArc {
+        let type_registry = TypeRegistryArc::default();
+        type_registry.write().register::<LightingProfile2d>();
+        type_registry
+    }
+
+    #[test]
+    fn partial_profile() {
+        let profile = deserialize_profile(
+            br#"(
+                settings: (blur: 8.0),
+                point_lights: {
+                    "torch": (radius: 120.0),
+                },
+            )"#,
+            &type_registry(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            profile,
+            LightingProfile2d {
+                settings: Lighting2dSettings {
+                    blur: 8.0,
+                    ..default()
+                },
+                point_lights: HashMap::from([(
+                    "torch".to_string(),
+                    PointLight2d {
+                        radius: 120.0,
+                        ..default()
+                    }
+                )]),
+                ..default()
+            }
+        );
+        assert!(profile.point_light("lamp").is_none());
+    }
+
+    #[test]
+    fn invalid_profile() {
+        let error = deserialize_profile(b"(settings: (blur: \"high\"))", &type_registry());
+
+        assert!(matches!(error, Err(LightingProfile2dLoaderError::Ron(_))));
+    }
+
+    #[test]
+    fn day_night_cycle_keeps_ambient_light() {
+        let mut world = World::new();
+        world.init_resource::<Events<AssetEvent<LightingProfile2d>>>();
+
+        let mut profiles = Assets::<LightingProfile2d>::default();
+        let handle = profiles.add(LightingProfile2d {
+            settings: Lighting2dSettings {
+                blur: 8.0,
+                ..default()
+            },
+            ambient_light: AmbientLight2d {
+                brightness: 0.2,
+                ..default()
+            },
+            ..default()
+        });
+        world.insert_resource(profiles);
+
+        let cycle = DayNightCycle2d::default();
+        let cycle_camera = world
+            .spawn((handle.clone(), cycle.clone(), cycle.ambient_light()))
+            .id();
+        let camera = world.spawn(handle).id();
+
+        world.run_system_once(apply_lighting_profiles);
+
+        assert_eq!(
+            world.get::<Lighting2dSettings>(cycle_camera).unwrap().blur,
+            8.0
+        );
+        assert_eq!(
+            world.get::<AmbientLight2d>(cycle_camera),
+            Some(&cycle.ambient_light())
+        );
+        assert_eq!(world.get::<AmbientLight2d>(camera).unwrap().brightness, 0.2);
+    }
+}
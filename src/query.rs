@@ -0,0 +1,461 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use crate::{
+    pipeline::MAX_DISTANCE,
+    prelude::{
+        AmbientLight2d, FieldOfView2d, LightOccluder2d, Lighting2dSettings, PointLight2d,
+        RaymarchSettings, VisibilityPolygon2d,
+    },
+    visibility::visibility_polygon,
+};
+
+/// Evaluates the lighting at world positions on the CPU, for gameplay like stealth detection.
+///
+/// It uses the same formulas as the lighting shaders: ambient light, point light attenuation and
+/// shadows raymarched through the distance field of the occluders. The jitter always uses the
+/// [`JitterSource::Hash`](crate::prelude::JitterSource::Hash) source, and the blur, light budgets,
+/// fog volumes, scattering, ambient occlusion and global illumination are not accounted for.
+/// Lights and occluders hidden with [`Visibility`] are ignored, whether they're on screen or not.
+///
+/// It also computes what can be seen from a position through the occluders, for line of sight.
+#[derive(SystemParam)]
+pub struct LightingQuery2d<'w, 's> {
+    views: Query<
+        'w,
+        's,
+        (
+            Entity,
+            &'static Lighting2dSettings,
+            Option<&'static AmbientLight2d>,
+        ),
+        With<Camera2d>,
+    >,
+    point_lights: Query<
+        'w,
+        's,
+        (
+            &'static PointLight2d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+    light_occluders: Query<
+        'w,
+        's,
+        (
+            &'static LightOccluder2d,
+            &'static GlobalTransform,
+            &'static InheritedVisibility,
+        ),
+    >,
+}
+
+impl LightingQuery2d<'_, '_> {
+    /// The light reaching `pos` with the settings of the first camera with [`Lighting2dSettings`],
+    /// or `None` if there's no such camera.
+    pub fn light_at(&self, pos: Vec2) -> Option<LinearRgba> {
+        let (view, _, _) = self.views.iter().next()?;
+        self.light_at_for_view(view, pos)
+    }
+
+    /// The light reaching `pos` with the settings of the `view` camera, or `None` if it has no
+    /// [`Lighting2dSettings`].
+    pub fn light_at_for_view(&self, view: Entity, pos: Vec2) -> Option<LinearRgba> {
+        let (_, settings, ambient_light) = self.views.get(view).ok()?;
+        let ambient_light = ambient_light.cloned().unwrap_or_default();
+        let occluders = self.occluders();
+
+        let point_lights = self
+            .point_lights
+            .iter()
+            .filter(|(_, _, visibility)| visibility.get())
+            .map(|(point_light, transform, _)| (transform.translation().xy(), point_light));
+
+        Some(evaluate_light(
+            pos,
+            &ambient_light,
+            &settings.raymarch,
+            point_lights,
+            &occluders,
+        ))
+    }
+
+    /// Signed distance from `pos` to the closest occluder, negative inside occluders.
+    pub fn occluder_distance(&self, pos: Vec2) -> f32 {
+        occluders_distance(&self.occluders(), pos)
+    }
+
+    /// The region visible from `origin` through the occluders, clipped by `field_of_view`.
+    /// Occluders are axis aligned boxes, so the polygon is exact apart from the radius arc.
+    pub fn visibility_polygon(
+        &self,
+        origin: Vec2,
+        field_of_view: &FieldOfView2d,
+    ) -> VisibilityPolygon2d {
+        visibility_polygon(origin, &self.occluders(), field_of_view)
+    }
+
+    fn occluders(&self) -> Vec<(Vec2, Vec2)> {
+        self.light_occluders
+            .iter()
+            .filter(|(_, _, visibility)| visibility.get())
+            .map(|(occluder, transform, _)| (transform.translation().xy(), occluder.half_size))
+            .collect()
+    }
+}
+
+/// Light reaching `pos` from the ambient light and the `(center, light)` point lights, shadowed by
+/// the `(center, half_size)` occluders. Same as the lighting shader.
+pub(crate) fn evaluate_light<'a>(
+    pos: Vec2,
+    ambient_light: &AmbientLight2d,
+    raymarch_settings: &RaymarchSettings,
+    point_lights: impl IntoIterator<Item = (Vec2, &'a PointLight2d)>,
+    occluders: &[(Vec2, Vec2)],
+) -> LinearRgba {
+    let mut light = ambient_light.color.to_linear() * ambient_light.brightness;
+
+    // Point lights don't reach inside occluders
+    if occluders_distance(occluders, pos) <= 0.0 {
+        return light.with_alpha(1.0);
+    }
+
+    for (center, point_light) in point_lights {
+        let dist = center.distance(pos);
+
+        if dist >= point_light.radius {
+            continue;
+        }
+
+        let visibility = raymarch(center, point_light, pos, raymarch_settings, occluders);
+
+        light += point_light.color.to_linear() * attenuation(point_light, dist) * visibility;
+    }
+
+    light.with_alpha(1.0)
+}
+
+/// Signed distance to the closest `(center, half_size)` occluder, see `sdf.wgsl`
+pub(crate) fn occluders_distance(occluders: &[(Vec2, Vec2)], pos: Vec2) -> f32 {
+    occluders
+        .iter()
+        .map(|&(center, half_size)| {
+            let d = (center - pos).abs() - half_size;
+            d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0)
+        })
+        .fold(MAX_DISTANCE, f32::min)
+}
+
+// Attribution: https://lisyarus.github.io/blog/posts/point-light-attenuation.html
+fn attenuation(point_light: &PointLight2d, dist: f32) -> f32 {
+    let s = dist / point_light.radius;
+    if s > 1.0 {
+        return 0.0;
+    }
+    let s2 = s * s;
+    point_light.intensity * (1.0 - s2).powi(2) / (1.0 + point_light.falloff * s2)
+}
+
+fn distance_factor(point_light: &PointLight2d, dist: f32) -> f32 {
+    // 1.0 next to the light and 0.0 at light.radius away
+    let fade_ratio = 1.0 - (dist / point_light.radius).clamp(0.0, 1.0);
+    fade_ratio.powi(2)
+}
+
+/// See `raymarch` in `lighting.wgsl`
+fn raymarch(
+    center: Vec2,
+    point_light: &PointLight2d,
+    ray_origin: Vec2,
+    settings: &RaymarchSettings,
+    occluders: &[(Vec2, Vec2)],
+) -> f32 {
+    let jitter = settings.jitter_contrib;
+    let ray_direction = (center - ray_origin).normalize_or_zero();
+    let stop_at = ray_origin.distance(center);
+    let has_source = point_light.source_radius > 0.0;
+    let source_angle = point_light.source_radius / stop_at;
+
+    let mut ray_progress = 0.0;
+    let mut light_contrib = 1.0_f32;
+
+    for _ in 0..settings.max_steps {
+        // ray found target
+        if ray_progress > stop_at {
+            if has_source {
+                light_contrib = smoothstep(-1.0, 1.0, light_contrib);
+            }
+
+            return light_contrib * distance_factor(point_light, stop_at);
+        }
+
+        let dist = occluders_distance(occluders, ray_origin + ray_progress * ray_direction);
+        let mut step = dist;
+
+        if has_source {
+            // -1.0 when the source is fully hidden, 1.0 when it's fully visible
+            light_contrib = light_contrib.min(dist / (ray_progress * source_angle));
+
+            if light_contrib <= -1.0 {
+                break;
+            }
+
+            step = dist.abs().max(1.0);
+        } else {
+            // ray found occluder
+            if dist <= 0.0 {
+                break;
+            }
+
+            light_contrib = light_contrib.min(dist / ray_progress * settings.sharpness);
+        }
+
+        let step_noise = fract(dist * 43758.547);
+        ray_progress += step * (1.0 - jitter) + jitter * step_noise;
+    }
+
+    0.0
+}
+
+/// `fract` of WGSL, always positive
+fn fract(x: f32) -> f32 {
+    x - x.floor()
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::SystemState;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-4;
+
+    fn dark() -> AmbientLight2d {
+        AmbientLight2d {
+            color: Color::WHITE,
+            brightness: 0.0,
+        }
+    }
+
+    fn light(radius: f32) -> PointLight2d {
+        PointLight2d {
+            color: Color::WHITE,
+            intensity: 1.0,
+            radius,
+            falloff: 0.0,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn ambient_only() {
+        let ambient_light = AmbientLight2d {
+            color: Color::LinearRgba(LinearRgba::rgb(1.0, 0.5, 0.25)),
+            brightness: 0.5,
+        };
+
+        let light = evaluate_light(Vec2::ZERO, &ambient_light, &default(), [], &[]).to_vec3();
+
+        assert!(light.abs_diff_eq(Vec3::new(0.5, 0.25, 0.125), EPSILON));
+    }
+
+    #[test]
+    fn unshadowed_attenuation() {
+        let point_light = light(100.0);
+
+        // Attenuated by (1 - s^2)^2 and faded by (1 - s)^2, with s the distance over the radius
+        let at_light = evaluate_light(
+            Vec2::ZERO,
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &[],
+        );
+        let half_way = evaluate_light(
+            Vec2::new(50.0, 0.0),
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &[],
+        );
+
+        assert!((at_light.red - 1.0).abs() < EPSILON);
+        assert!((half_way.red - 0.5625 * 0.25).abs() < EPSILON);
+    }
+
+    #[test]
+    fn out_of_radius() {
+        let point_light = light(100.0);
+
+        let light = evaluate_light(
+            Vec2::new(150.0, 0.0),
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &[],
+        );
+
+        assert_eq!(light.red, 0.0);
+    }
+
+    #[test]
+    fn occluder_casts_shadow() {
+        let point_light = light(200.0);
+        let occluders = [(Vec2::new(50.0, 0.0), Vec2::splat(10.0))];
+
+        let lit = evaluate_light(
+            Vec2::new(0.0, 100.0),
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &occluders,
+        );
+        let shadowed = evaluate_light(
+            Vec2::new(100.0, 0.0),
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &occluders,
+        );
+
+        assert!(lit.red > 0.1);
+        assert_eq!(shadowed.red, 0.0);
+    }
+
+    #[test]
+    fn inside_occluder_gets_ambient_only() {
+        let point_light = light(200.0);
+        let occluders = [(Vec2::new(50.0, 0.0), Vec2::splat(10.0))];
+        let ambient_light = AmbientLight2d {
+            color: Color::WHITE,
+            brightness: 0.2,
+        };
+
+        let light = evaluate_light(
+            Vec2::new(50.0, 0.0),
+            &ambient_light,
+            &default(),
+            [(Vec2::ZERO, &point_light)],
+            &occluders,
+        );
+
+        assert!((light.red - 0.2).abs() < EPSILON);
+    }
+
+    #[test]
+    fn source_radius_softens_shadow_edges() {
+        // Barely covering the line between light and receiver
+        let occluders = [(Vec2::new(50.0, -5.0), Vec2::splat(10.0))];
+        let receiver = Vec2::new(100.0, 0.0);
+
+        let hard = evaluate_light(
+            receiver,
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &light(200.0))],
+            &occluders,
+        );
+        let soft = evaluate_light(
+            receiver,
+            &dark(),
+            &default(),
+            [(
+                Vec2::ZERO,
+                &PointLight2d {
+                    source_radius: 40.0,
+                    ..light(200.0)
+                },
+            )],
+            &occluders,
+        );
+
+        // The shadow of a point light is hard, but part of a larger source stays visible
+        assert_eq!(hard.red, 0.0);
+        assert!(soft.red > 0.0);
+    }
+
+    #[test]
+    fn colored_lights_add_up() {
+        let red = PointLight2d {
+            color: Color::LinearRgba(LinearRgba::RED),
+            ..light(100.0)
+        };
+        let blue = PointLight2d {
+            color: Color::LinearRgba(LinearRgba::BLUE),
+            ..light(100.0)
+        };
+
+        let light = evaluate_light(
+            Vec2::ZERO,
+            &dark(),
+            &default(),
+            [(Vec2::ZERO, &red), (Vec2::ZERO, &blue)],
+            &[],
+        );
+
+        assert!(light
+            .to_vec3()
+            .abs_diff_eq(Vec3::new(1.0, 0.0, 1.0), EPSILON));
+    }
+
+    #[test]
+    fn occluder_distance() {
+        let occluders = [
+            (Vec2::ZERO, Vec2::new(10.0, 5.0)),
+            (Vec2::new(100.0, 0.0), Vec2::splat(10.0)),
+        ];
+
+        assert_eq!(occluders_distance(&[], Vec2::ZERO), MAX_DISTANCE);
+        assert!((occluders_distance(&occluders, Vec2::new(20.0, 0.0)) - 10.0).abs() < EPSILON);
+        assert!((occluders_distance(&occluders, Vec2::ZERO) + 5.0).abs() < EPSILON);
+        assert!((occluders_distance(&occluders, Vec2::new(80.0, 0.0)) - 10.0).abs() < EPSILON);
+    }
+
+    #[test]
+    fn system_param_reads_the_world() {
+        let mut world = World::new();
+
+        world.spawn((
+            Camera2dBundle::default(),
+            Lighting2dSettings::default(),
+            AmbientLight2d {
+                color: Color::WHITE,
+                brightness: 0.1,
+            },
+        ));
+        world.spawn((
+            light(100.0),
+            GlobalTransform::default(),
+            InheritedVisibility::VISIBLE,
+        ));
+        world.spawn((
+            PointLight2d {
+                intensity: 10.0,
+                ..light(100.0)
+            },
+            GlobalTransform::default(),
+            InheritedVisibility::HIDDEN,
+        ));
+        world.spawn((
+            LightOccluder2d::new(Vec2::splat(10.0)),
+            GlobalTransform::from_xyz(50.0, 0.0, 0.0),
+            InheritedVisibility::VISIBLE,
+        ));
+
+        let mut state = SystemState::<LightingQuery2d>::new(&mut world);
+        let query = state.get(&world);
+
+        let at_light = query.light_at(Vec2::ZERO).unwrap();
+        let shadowed = query.light_at(Vec2::new(80.0, 0.0)).unwrap();
+
+        // The hidden light is ignored
+        assert!((at_light.red - 1.1).abs() < EPSILON);
+        assert!((shadowed.red - 0.1).abs() < EPSILON);
+        assert!((query.occluder_distance(Vec2::new(70.0, 0.0)) - 10.0).abs() < EPSILON);
+    }
+}
@@ -0,0 +1,366 @@
+use std::{
+    collections::VecDeque,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+};
+
+use bevy::{
+    ecs::entity::EntityHashMap,
+    prelude::*,
+    render::{
+        render_resource::{
+            Buffer, BufferAsyncError, BufferDescriptor, BufferUsages, Maintain, MapMode,
+            TextureFormat,
+        },
+        renderer::RenderDevice,
+        view::{ExtractedView, ViewTarget},
+        Extract,
+    },
+};
+
+use crate::{extract::ExtractedLighting2dSettings, pipeline::Lighting2dRenderConfig};
+
+/// Bytes between the texels copied to a readback buffer, the alignment of texture copy rows
+/// (`wgpu::COPY_BYTES_PER_ROW_ALIGNMENT`)
+pub const READBACK_TEXEL_STRIDE: usize = 256;
+
+/// Frames of readbacks waiting for the GPU at most. New readbacks are skipped past it, so a GPU
+/// falling behind doesn't pile up buffers.
+const MAX_FRAMES_IN_FLIGHT: usize = 3;
+
+/// Light reaching an entity, read back from the lighting texture of the GPU so it matches what's
+/// on screen exactly, for gameplay like plants only growing in lit areas.
+///
+/// It's updated in [`PreUpdate`], with at least a frame of latency since the GPU isn't waited
+/// for. The light is `None` if the entity is outside of every camera with [`Lighting2dSettings`],
+/// or until the first readback is done.
+///
+/// Each illuminated entity on screen costs a texel copy command and 256 bytes of mapped memory
+/// every frame, so it suits a few hundred entities rather than thousands. Readbacks are skipped
+/// while the GPU is 3 frames behind.
+///
+/// [`Lighting2dSettings`]: crate::prelude::Lighting2dSettings
+#[derive(Component, Clone, Copy, Default, Debug, PartialEq, Reflect)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
+pub struct Illuminated2d {
+    /// The light at the entity's position, multiplying the color of the scene there.
+    pub light: Option<LinearRgba>,
+}
+
+/// The light of every illuminated entity on screen, from the latest readback. Written by the render
+/// world and taken by the main world.
+#[derive(Resource, Clone, Default)]
+pub struct IlluminationResults(Arc<Mutex<Option<EntityHashMap<LinearRgba>>>>);
+
+pub fn update_illuminated(
+    results: Res<IlluminationResults>,
+    mut illuminated_query: Query<(Entity, &mut Illuminated2d)>,
+) {
+    let Some(results) = results.0.lock().ok().and_then(|mut results| results.take()) else {
+        return;
+    };
+
+    for (entity, mut illuminated) in &mut illuminated_query {
+        illuminated.set_if_neq(Illuminated2d {
+            light: results.get(&entity).copied(),
+        });
+    }
+}
+
+#[derive(Resource, Default)]
+pub struct ExtractedIlluminated2d(Vec<(Entity, Vec2)>);
+
+pub fn extract_illuminated(
+    mut extracted: ResMut<ExtractedIlluminated2d>,
+    illuminated_query: Extract<Query<(Entity, &GlobalTransform), With<Illuminated2d>>>,
+) {
+    extracted.0.clear();
+    extracted.0.extend(
+        illuminated_query
+            .iter()
+            .map(|(entity, transform)| (entity, transform.translation().xy())),
+    );
+}
+
+/// Texels of the final lighting texture copied to `buffer`, each at a multiple of
+/// [`READBACK_TEXEL_STRIDE`]
+#[derive(Component)]
+pub struct ViewIlluminationReadback {
+    pub buffer: Buffer,
+    pub texels: Vec<UVec2>,
+    /// Set by the render graph once the copy is recorded. It isn't when the lighting node skips
+    /// the view, while its pipelines compile.
+    pub written: Arc<AtomicBool>,
+}
+
+/// Readbacks waiting for the GPU, oldest first, and the buffers they're done with
+#[derive(Resource, Default)]
+pub struct IlluminationReadbacks {
+    frames: VecDeque<Vec<ReadbackBatch>>,
+    buffers: Vec<Buffer>,
+}
+
+struct ReadbackBatch {
+    buffer: Buffer,
+    entities: Vec<Entity>,
+    written: Arc<AtomicBool>,
+    mapped: Option<Arc<OnceLock<Result<(), BufferAsyncError>>>>,
+}
+
+pub fn prepare_illumination_readbacks(
+    mut commands: Commands,
+    render_device: Res<RenderDevice>,
+    illuminated: Res<ExtractedIlluminated2d>,
+    mut readbacks: ResMut<IlluminationReadbacks>,
+    views_query: Query<(Entity, &ExtractedView, &ViewTarget), With<ExtractedLighting2dSettings>>,
+) {
+    if illuminated.0.is_empty() || readbacks.frames.len() >= MAX_FRAMES_IN_FLIGHT {
+        return;
+    }
+
+    let mut frame = Vec::new();
+
+    for (view_entity, view, view_target) in &views_query {
+        let clip_from_world = view.clip_from_world.unwrap_or_else(|| {
+            view.clip_from_view * view.world_from_view.compute_matrix().inverse()
+        });
+        let size = view_target.main_texture().size();
+        let viewport = view.viewport.as_vec4();
+
+        let (entities, texels): (Vec<_>, Vec<_>) = illuminated
+            .0
+            .iter()
+            .filter_map(|&(entity, pos)| {
+                let ndc = clip_from_world.project_point3(pos.extend(0.0)).xy();
+
+                if ndc.abs().max_element() > 1.0 {
+                    return None;
+                }
+
+                // Same as `ndc_to_frag_coord` of `view_transformations.wgsl`
+                let uv = ndc * Vec2::new(0.5, -0.5) + 0.5;
+                let texel = (viewport.xy() + uv * viewport.zw())
+                    .as_uvec2()
+                    .min(UVec2::new(size.width, size.height) - 1);
+
+                Some((entity, texel))
+            })
+            .unzip();
+
+        if entities.is_empty() {
+            continue;
+        }
+
+        let buffer_size = (entities.len() * READBACK_TEXEL_STRIDE) as u64;
+        let buffer = match readbacks
+            .buffers
+            .iter()
+            .position(|buffer| buffer.size() >= buffer_size)
+        {
+            Some(index) => readbacks.buffers.swap_remove(index),
+            None => render_device.create_buffer(&BufferDescriptor {
+                label: Some("illumination_readback_buffer"),
+                size: buffer_size.next_power_of_two(),
+                usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+                mapped_at_creation: false,
+            }),
+        };
+
+        let written = Arc::new(AtomicBool::new(false));
+
+        commands
+            .entity(view_entity)
+            .insert(ViewIlluminationReadback {
+                buffer: buffer.clone(),
+                texels,
+                written: written.clone(),
+            });
+
+        frame.push(ReadbackBatch {
+            buffer,
+            entities,
+            written,
+            mapped: None,
+        });
+    }
+
+    // Pushed even if empty so entities leaving every view are reset in order
+    readbacks.frames.push_back(frame);
+}
+
+/// Maps the buffers copied to this frame, once the GPU work has been submitted. The frame is
+/// dropped if a copy wasn't recorded, keeping the previous results instead of publishing garbage.
+pub fn map_illumination_readbacks(mut readbacks: ResMut<IlluminationReadbacks>) {
+    let readbacks = &mut *readbacks;
+
+    let Some(frame) = readbacks.frames.back_mut() else {
+        return;
+    };
+
+    // The back frame is already mapped if none was prepared, while too many are in flight
+    if frame
+        .iter()
+        .any(|batch| batch.mapped.is_none() && !batch.written.load(Ordering::Relaxed))
+    {
+        let frame = readbacks.frames.pop_back().unwrap();
+        readbacks
+            .buffers
+            .extend(frame.into_iter().map(|batch| batch.buffer));
+        return;
+    }
+
+    for batch in frame.iter_mut().filter(|batch| batch.mapped.is_none()) {
+        let mapped = Arc::new(OnceLock::new());
+        let callback_mapped = mapped.clone();
+
+        batch
+            .buffer
+            .slice(..)
+            .map_async(MapMode::Read, move |result| {
+                let _ = callback_mapped.set(result);
+            });
+
+        batch.mapped = Some(mapped);
+    }
+}
+
+/// Publishes the readbacks the GPU is done with, without waiting for the others
+pub fn receive_illumination_readbacks(
+    render_device: Res<RenderDevice>,
+    config: Res<Lighting2dRenderConfig>,
+    results: Res<IlluminationResults>,
+    mut readbacks: ResMut<IlluminationReadbacks>,
+) {
+    if readbacks.frames.is_empty() {
+        return;
+    }
+
+    render_device.poll(Maintain::Poll);
+
+    let mut latest = None;
+
+    while let Some(frame) = readbacks.frames.front() {
+        let done = frame.iter().all(|batch| {
+            batch
+                .mapped
+                .as_ref()
+                .is_some_and(|mapped| mapped.get().is_some())
+        });
+
+        if !done {
+            break;
+        }
+
+        let frame = readbacks.frames.pop_front().unwrap();
+        let mut lights = EntityHashMap::default();
+
+        for batch in frame {
+            let mapped = batch.mapped.as_ref().and_then(|mapped| mapped.get());
+
+            if let Some(Ok(())) = mapped {
+                let data = batch.buffer.slice(..).get_mapped_range();
+
+                for (index, entity) in batch.entities.iter().enumerate() {
+                    let offset = index * READBACK_TEXEL_STRIDE;
+                    lights.insert(
+                        *entity,
+                        decode_lighting_texel(config.lighting_format, &data[offset..]),
+                    );
+                }
+
+                drop(data);
+                batch.buffer.unmap();
+                readbacks.buffers.push(batch.buffer);
+            }
+        }
+
+        latest = Some(lights);
+    }
+
+    if let (Some(lights), Ok(mut results)) = (latest, results.0.lock()) {
+        *results = Some(lights);
+    }
+}
+
+/// Decodes a texel of the lighting texture, `Rgba16Float` or `Rg11b10Float`
+fn decode_lighting_texel(format: TextureFormat, bytes: &[u8]) -> LinearRgba {
+    match format {
+        TextureFormat::Rg11b10Float => {
+            let bits = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+
+            LinearRgba::rgb(
+                unsigned_float_to_f32(bits & 0x7ff, 6),
+                unsigned_float_to_f32((bits >> 11) & 0x7ff, 6),
+                unsigned_float_to_f32(bits >> 22, 5),
+            )
+        }
+        _ => {
+            let channel = |index: usize| {
+                f16_to_f32(u16::from_le_bytes([bytes[index * 2], bytes[index * 2 + 1]]))
+            };
+
+            // The alpha of the lighting texture isn't a light value
+            LinearRgba::rgb(channel(0), channel(1), channel(2))
+        }
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 == 0 { 1.0 } else { -1.0 };
+    let exponent = i32::from((bits >> 10) & 0x1f);
+    let mantissa = f32::from(bits & 0x3ff);
+
+    sign * match exponent {
+        0 => mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+/// Decodes the 11 and 10 bit floats of `Rg11b10Float`, with a 5 bit exponent and no sign
+fn unsigned_float_to_f32(bits: u32, mantissa_bits: u32) -> f32 {
+    let exponent = (bits >> mantissa_bits) as i32;
+    let mantissa = (bits & ((1 << mantissa_bits) - 1)) as f32 / (1 << mantissa_bits) as f32;
+
+    match exponent {
+        0 => mantissa * 2f32.powi(-14),
+        0x1f if mantissa == 0.0 => f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => (1.0 + mantissa) * 2f32.powi(exponent - 15),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_half_floats() {
+        assert_eq!(f16_to_f32(0x0000), 0.0);
+        assert_eq!(f16_to_f32(0x3c00), 1.0);
+        assert_eq!(f16_to_f32(0xc000), -2.0);
+        assert_eq!(f16_to_f32(0x3555), 0.33325195);
+        assert_eq!(f16_to_f32(0x7bff), 65504.0);
+        assert_eq!(f16_to_f32(0x0001), 2f32.powi(-24));
+        assert_eq!(f16_to_f32(0x7c00), f32::INFINITY);
+    }
+
+    #[test]
+    fn decodes_rg11b10_floats() {
+        // Red 1.0, green 0.5 and blue 2.0
+        let bits: u32 = 0x3c0 | (0x380 << 11) | (0x200 << 22);
+        let light = decode_lighting_texel(TextureFormat::Rg11b10Float, &bits.to_le_bytes());
+
+        assert_eq!(light, LinearRgba::rgb(1.0, 0.5, 2.0));
+        assert_eq!(unsigned_float_to_f32(0x7bf, 6), 65024.0);
+        assert_eq!(unsigned_float_to_f32(0x001, 5), 2f32.powi(-19));
+    }
+}
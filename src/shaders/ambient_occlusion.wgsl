@@ -0,0 +1,69 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_lit::{
+    types::Lighting2dSettings,
+    view_transformations::{frag_coord_to_ndc, position_ndc_to_world, position_world_to_ndc, ndc_to_uv},
+}
+
+@group(0) @binding(1) var<uniform> settings: Lighting2dSettings;
+@group(0) @binding(2) var sdf: texture_2d<f32>;
+@group(0) @binding(3) var sdf_sampler: sampler;
+
+const TAU: f32 = 6.28318530718;
+const MAX_STEPS: u32 = 8u;
+
+// The output is subtracted from the lighting texture, removing the occluded share of the ambient
+// light it was cleared to
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let pos = position_ndc_to_world(frag_coord_to_ndc(in.position)).xy;
+    let radius = settings.ambient_occlusion_radius;
+    let samples = settings.ambient_occlusion_samples;
+
+    // Inside occluders, or no occluder close enough
+    let dist = get_distance(pos);
+    if dist <= 0.0 || dist >= radius || samples == 0u {
+        return vec4(0.0);
+    }
+
+    // Rotates the directions of each pixel to trade banding for noise
+    // Attribution: https://blog.demofox.org/2022/01/01/interleaved-gradient-noise-a-different-kind-of-low-discrepancy-sequence
+    let noise = fract(52.9829189 * fract(dot(in.position.xy, vec2(0.06711056, 0.00583715))));
+
+    var occlusion = 0.0;
+
+    for (var i = 0u; i < samples; i++) {
+        let angle = (f32(i) + noise) / f32(samples) * TAU;
+        occlusion += direction_occlusion(pos, vec2(cos(angle), sin(angle)), radius);
+    }
+
+    occlusion = saturate(occlusion / f32(samples) * settings.ambient_occlusion_intensity);
+
+    return vec4(settings.ambient_light.rgb * occlusion, 0.0);
+}
+
+// Sphere traces the SDF towards `direction`, 1.0 when an occluder is touching `pos` and 0.0 when
+// none is hit within `radius`
+fn direction_occlusion(pos: vec2<f32>, direction: vec2<f32>, radius: f32) -> f32 {
+    var progress = 0.0;
+
+    for (var i = 0u; i < MAX_STEPS; i++) {
+        let dist = get_distance(pos + direction * progress);
+
+        if dist <= 0.0 {
+            return 1.0 - progress / radius;
+        }
+
+        progress += max(dist, 1.0);
+
+        if progress >= radius {
+            break;
+        }
+    }
+
+    return 0.0;
+}
+
+fn get_distance(pos: vec2<f32>) -> f32 {
+    let uv = ndc_to_uv(position_world_to_ndc(vec3(pos, 0.0)).xy);
+    return textureSampleLevel(sdf, sdf_sampler, uv, 0.0).r;
+}
@@ -6,9 +6,61 @@
 @group(0) @binding(1) var<uniform> settings: Lighting2dSettings;
 @group(0) @binding(2) var texture: texture_2d<f32>;
 @group(0) @binding(3) var texture_sampler: sampler;
+@group(0) @binding(4) var sdf: texture_2d<f32>;
 
 @fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+fn gaussian_horizontal(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return gaussian_blur(in.position, get_coc(), vec2(1.0, 0.0));
+}
+
+@fragment
+fn gaussian_vertical(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return gaussian_blur(in.position, get_coc(), vec2(0.0, 1.0));
+}
+
+@fragment
+fn bilateral_horizontal(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return bilateral_blur(in.position, get_coc(), vec2(1.0, 0.0));
+}
+
+@fragment
+fn bilateral_vertical(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    return bilateral_blur(in.position, get_coc(), vec2(0.0, 1.0));
+}
+
+// Renders to a target half the size of the source, averaging four bilinear samples around the
+// center sample
+@fragment
+fn kawase_downsample(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let texel = 1.0 / vec2<f32>(textureDimensions(texture));
+
+    var sum = sample(in.uv) * 4.0;
+    sum += sample(in.uv - texel);
+    sum += sample(in.uv + texel);
+    sum += sample(in.uv + vec2(texel.x, -texel.y));
+    sum += sample(in.uv - vec2(texel.x, -texel.y));
+
+    return vec4(sum / 8.0, 1.0);
+}
+
+// Renders to a target twice the size of the source with a tent filter
+@fragment
+fn kawase_upsample(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let half_texel = 0.5 / vec2<f32>(textureDimensions(texture));
+
+    var sum = sample(in.uv + vec2(-half_texel.x * 2.0, 0.0));
+    sum += sample(in.uv + vec2(half_texel.x * 2.0, 0.0));
+    sum += sample(in.uv + vec2(0.0, -half_texel.y * 2.0));
+    sum += sample(in.uv + vec2(0.0, half_texel.y * 2.0));
+    sum += sample(in.uv + vec2(-half_texel.x, half_texel.y)) * 2.0;
+    sum += sample(in.uv + vec2(half_texel.x, half_texel.y)) * 2.0;
+    sum += sample(in.uv + vec2(half_texel.x, -half_texel.y)) * 2.0;
+    sum += sample(in.uv + vec2(-half_texel.x, -half_texel.y)) * 2.0;
+
+    return vec4(sum / 12.0, 1.0);
+}
+
+fn get_coc() -> f32 {
     var coc = settings.coc;
 
     if !bool(settings.fixed_resolution) {
@@ -17,10 +69,11 @@ fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
         coc *= screen_diagonal / 2000.0;
     }
 
-    let x = gaussian_blur(in.position, coc, vec2(1.0, 0.0));
-    let y = gaussian_blur(in.position, coc, vec2(0.0, 1.0));
+    return coc;
+}
 
-    return mix(x, y, 0.5);
+fn sample(uv: vec2<f32>) -> vec3<f32> {
+    return textureSampleLevel(texture, texture_sampler, uv, 0.0).rgb;
 }
 
 // ATTRIBUTION: The code for this function was originally
@@ -51,3 +104,38 @@ fn gaussian_blur(frag_coord: vec4<f32>, coc: f32, frag_offset: vec2<f32>) -> vec
 
     return vec4(sum / weight_sum, 1.0);
 }
+
+// A gaussian blur that walks away from the center in both directions and stops at the first
+// sample inside an occluder, so light never crosses solid geometry
+fn bilateral_blur(frag_coord: vec4<f32>, coc: f32, frag_offset: vec2<f32>) -> vec4<f32> {
+    let sigma = coc * 0.25;
+    let support = i32(ceil(sigma * 1.5));
+    let uv = frag_coord.xy / vec2<f32>(textureDimensions(texture));
+    let offset = frag_offset / vec2<f32>(textureDimensions(texture));
+    let exp_factor = -1.0 / (2.0 * sigma * sigma);
+
+    var sum = sample(uv);
+
+    if textureSampleLevel(sdf, texture_sampler, uv, 0.0).r <= 0.0 {
+        return vec4(sum, 1.0);
+    }
+
+    var weight_sum = 1.0;
+
+    for (var side = -1.0; side <= 1.0; side += 2.0) {
+        for (var i = 1; i <= support; i++) {
+            let sample_uv = uv + offset * f32(i) * side;
+
+            if textureSampleLevel(sdf, texture_sampler, sample_uv, 0.0).r <= 0.0 {
+                break;
+            }
+
+            let weight = exp(exp_factor * f32(i) * f32(i));
+
+            sum += sample(sample_uv) * weight;
+            weight_sum += weight;
+        }
+    }
+
+    return vec4(sum / weight_sum, 1.0);
+}
@@ -0,0 +1,173 @@
+// Global illumination with radiance cascades
+// Attribution: https://github.com/Raikiri/RadianceCascadesPaper
+//
+// Cascade `n` has probes every `probe_spacing * 2^n` pixels, each casting `4^(n + 1)` rays over
+// the interval from `interval * (4^n - 1) / 3` to `interval * (4^(n + 1) - 1) / 3` pixels. The
+// rays of a probe are stored in a block of `2^(n + 1)` by `2^(n + 1)` texels. Cascades are traced
+// from the last to the first, and rays that don't hit anything continue with the radiance of the
+// matching rays of the next cascade.
+
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_lit::{
+    types::Lighting2dSettings,
+    view_transformations::{frag_coord_to_ndc, position_ndc_to_world},
+}
+
+struct GiCascade {
+    cascade: u32,
+    cascade_count: u32,
+    probe_spacing: f32,
+    interval: f32,
+    max_steps: u32,
+    intensity: f32,
+    // Size of the lighting texture
+    size: vec2<f32>,
+}
+
+@group(0) @binding(1) var<uniform> settings: Lighting2dSettings;
+@group(0) @binding(2) var<uniform> gi: GiCascade;
+@group(0) @binding(3) var texture_sampler: sampler;
+@group(0) @binding(4) var cascade_texture: texture_2d<f32>;
+// Only bound for `trace`
+@group(0) @binding(5) var sdf: texture_2d<f32>;
+@group(0) @binding(6) var lighting: texture_2d<f32>;
+@group(0) @binding(7) var albedo: texture_2d<f32>;
+@group(0) @binding(8) var emissive: texture_2d<f32>;
+
+const TAU: f32 = 6.28318530718;
+
+// Traces the rays of cascade `gi.cascade`, merged with the next cascade read from `cascade_texture`
+@fragment
+fn trace(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let cascade = gi.cascade;
+    let side = 2u << cascade;
+    let texel = vec2<u32>(in.position.xy);
+    let probe = texel / side;
+    let ray_coord = texel % side;
+    let ray = ray_coord.x + ray_coord.y * side;
+
+    let angle = (f32(ray) + 0.5) / f32(side * side) * TAU;
+    let direction = vec2(cos(angle), sin(angle));
+    let origin = (vec2<f32>(probe) + 0.5) * probe_spacing(cascade);
+
+    let interval_start = gi.interval * (f32(1u << (2u * cascade)) - 1.0) / 3.0;
+    let interval_end = gi.interval * (f32(1u << (2u * (cascade + 1u))) - 1.0) / 3.0;
+
+    let size = gi.size;
+    let world_per_pixel = distance(
+        position_ndc_to_world(frag_coord_to_ndc(vec4(0.0, 0.0, 0.0, 1.0))),
+        position_ndc_to_world(frag_coord_to_ndc(vec4(1.0, 0.0, 0.0, 1.0))),
+    );
+
+    var progress = interval_start;
+
+    for (var i = 0u; i < gi.max_steps; i++) {
+        let pos = origin + direction * progress;
+
+        if any(pos < vec2(0.0)) || any(pos >= size) {
+            return vec4(0.0, 0.0, 0.0, 1.0);
+        }
+
+        let dist = textureSampleLevel(sdf, texture_sampler, pos / size, 0.0).r / world_per_pixel;
+
+        if dist <= 0.5 {
+            return vec4(surface_radiance(pos, direction, size), 1.0);
+        }
+
+        progress += dist;
+
+        if progress >= interval_end {
+            break;
+        }
+    }
+
+    if cascade + 1u >= gi.cascade_count {
+        return vec4(0.0, 0.0, 0.0, 1.0);
+    }
+
+    return vec4(merge(origin, ray), 1.0);
+}
+
+// Light leaving the surface hit at `pos`: its emission plus the direct light it reflects
+fn surface_radiance(pos: vec2<f32>, direction: vec2<f32>, size: vec2<f32>) -> vec3<f32> {
+    let inside_uv = (pos + direction) / size;
+    let outside_uv = (pos - direction * 2.0) / size;
+
+    let surface_albedo = textureSampleLevel(albedo, texture_sampler, inside_uv, 0.0).rgb;
+    let surface_emissive = textureSampleLevel(emissive, texture_sampler, inside_uv, 0.0).rgb;
+
+    // The lighting texture is cleared to the ambient light, only direct light bounces
+    let direct = max(
+        textureSampleLevel(lighting, texture_sampler, outside_uv, 0.0).rgb -
+            settings.ambient_light.rgb,
+        vec3(0.0),
+    );
+
+    return surface_emissive + surface_albedo * direct;
+}
+
+// Radiance of the 4 rays of the next cascade matching `ray`, interpolated between its 4 closest
+// probes
+fn merge(origin: vec2<f32>, ray: u32) -> vec3<f32> {
+    let upper = gi.cascade + 1u;
+    let upper_side = 2u << upper;
+    let probe_count = vec2<i32>(ceil(gi.size / probe_spacing(upper)));
+
+    let grid = origin / probe_spacing(upper) - 0.5;
+    let base = vec2<i32>(floor(grid));
+    let weight = fract(grid);
+
+    var radiance = vec3(0.0);
+
+    for (var y = 0; y < 2; y++) {
+        for (var x = 0; x < 2; x++) {
+            let offset = vec2(x, y);
+            let probe = vec2<u32>(clamp(base + offset, vec2(0), probe_count - 1));
+            let bilinear = mix(1.0 - weight, weight, vec2<f32>(offset));
+
+            radiance += probe_radiance(probe, upper_side, ray * 4u, 4u) *
+                bilinear.x * bilinear.y;
+        }
+    }
+
+    return radiance;
+}
+
+// Adds the irradiance of the first cascade to the lighting
+@fragment
+fn apply(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let grid = in.position.xy / probe_spacing(0u) - 0.5;
+    let probe_count = vec2<i32>(ceil(gi.size / probe_spacing(0u)));
+    let base = vec2<i32>(floor(grid));
+    let weight = fract(grid);
+
+    var irradiance = vec3(0.0);
+
+    for (var y = 0; y < 2; y++) {
+        for (var x = 0; x < 2; x++) {
+            let offset = vec2(x, y);
+            let probe = vec2<u32>(clamp(base + offset, vec2(0), probe_count - 1));
+            let bilinear = mix(1.0 - weight, weight, vec2<f32>(offset));
+
+            irradiance += probe_radiance(probe, 2u, 0u, 4u) * bilinear.x * bilinear.y;
+        }
+    }
+
+    return vec4(irradiance * gi.intensity, 0.0);
+}
+
+fn probe_spacing(cascade: u32) -> f32 {
+    return gi.probe_spacing * f32(1u << cascade);
+}
+
+// Average radiance of `count` consecutive rays of a probe
+fn probe_radiance(probe: vec2<u32>, side: u32, first_ray: u32, count: u32) -> vec3<f32> {
+    var radiance = vec3(0.0);
+
+    for (var ray = first_ray; ray < first_ray + count; ray++) {
+        let coord = probe * side + vec2(ray % side, ray / side);
+        radiance += textureLoad(cascade_texture, coord, 0).rgb;
+    }
+
+    return radiance / f32(count);
+}
@@ -1,6 +1,6 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_lit::{
-    types::{Lighting2dSettings, PointLight2d},
+    types::{FogVolume2d, Lighting2dSettings, PointLight2d},
     view_transformations::{
         frag_coord_to_ndc,
         position_ndc_to_world,
@@ -9,48 +9,267 @@
     }
 }
 
+// Surface lighting and light scattered in the air are rendered to separate targets so they can be
+// tuned independently
+struct LightingOutput {
+    @location(0) lighting: vec4<f32>,
+    @location(1) scattering: vec4<f32>,
+#ifdef DEBUG_STATS
+    // raymarch steps and lights covering the fragment, for the debug views
+    @location(2) stats: vec4<f32>,
+#endif
+}
+
+struct LightVolumeOutput {
+    @builtin(position) position: vec4<f32>,
+    @location(0) @interpolate(flat) light_index: u32,
+}
+
 @group(0) @binding(1) var<uniform> settings: Lighting2dSettings;
 
 #if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
     @group(0) @binding(2) var<storage> lights: array<PointLight2d>;
 #else
-    const MAX_LIGHTS: u32 = 82u;
+    const MAX_LIGHTS: u32 = #{MAX_LIGHTS}u;
 
+    // One batch of lights, unused slots are padded with lights without radius
     @group(0) @binding(2) var<uniform> lights: array<PointLight2d, MAX_LIGHTS>;
 #endif
 
 @group(0) @binding(3) var sdf: texture_2d<f32>;
 @group(0) @binding(4) var sdf_sampler: sampler;
+@group(0) @binding(5) var blue_noise: texture_2d<f32>;
+
+#if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
+    @group(0) @binding(6) var<storage> fog_volumes: array<FogVolume2d>;
+#else
+    const MAX_FOG_VOLUMES: u32 = #{MAX_FOG_VOLUMES}u;
+
+    // Only the first batch of fog volumes is available without storage buffers
+    @group(0) @binding(6) var<uniform> fog_volumes: array<FogVolume2d, MAX_FOG_VOLUMES>;
+#endif
+
+#ifdef DEBUG_STATS
+    // Steps taken by the last raymarch
+    var<private> raymarch_steps: f32;
+#endif
 
+const GOLDEN_RATIO_CONJUGATE: f32 = 0.61803398875;
+// World units between the lattice points of the fog noise
+const FOG_NOISE_SCALE: f32 = 64.0;
+// Fog volumes glow as if they were this thick towards the camera, in world units
+const FOG_THICKNESS: f32 = 64.0;
+
+// The output is added to the lighting texture, which is cleared to the ambient light
 @fragment
-fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+fn fragment(in: FullscreenVertexOutput) -> LightingOutput {
     let pos = position_ndc_to_world(frag_coord_to_ndc(in.position)).xy;
 
-    var lighting_color = vec4(settings.ambient_light.rgb, 1.0);
+    var out = empty_output();
 
     if get_distance(pos) <= 0.0 {
-        return lighting_color;
+        return out;
     }
 
 #if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
-    let light_count = arrayLength(&lights);
+    let light_count = settings.point_light_count;
 #else
     let light_count = MAX_LIGHTS;
 #endif
 
-    // TODO: skiping first point light that is a dummy. Find way to remove this.
-    for (var i = 1u; i < light_count; i++) {
-        let light = lights[i];
-        let dist = distance(light.center, pos);
+    let noise = jitter_noise(in.position.xy);
+
+    for (var i = 0u; i < light_count; i++) {
+        let contribution = light_contribution(lights[i], pos, noise);
+        out.lighting += contribution.lighting;
+        out.scattering += contribution.scattering;
+#ifdef DEBUG_STATS
+        out.stats += contribution.stats;
+#endif
+    }
+
+    return out;
+}
+
+// Draws the quad covering the radius of the light at `instance_index` as a triangle strip
+@vertex
+fn light_volume_vertex(
+    @builtin(vertex_index) vertex_index: u32,
+    @builtin(instance_index) instance_index: u32,
+) -> LightVolumeOutput {
+    let light = lights[instance_index];
+    let corner = vec2(f32(vertex_index & 1u), f32(vertex_index >> 1u)) * 2.0 - 1.0;
+    let ndc = position_world_to_ndc(vec3(light.center + corner * light.radius, 0.0));
+
+    var out: LightVolumeOutput;
+    out.position = vec4(ndc.xy, 0.0, 1.0);
+    out.light_index = instance_index;
+    return out;
+}
+
+// The output is added to the lighting texture, which is cleared to the ambient light
+@fragment
+fn light_volume_fragment(in: LightVolumeOutput) -> LightingOutput {
+    let pos = position_ndc_to_world(frag_coord_to_ndc(in.position)).xy;
+
+    if get_distance(pos) <= 0.0 {
+        return empty_output();
+    }
+
+    return light_contribution(lights[in.light_index], pos, jitter_noise(in.position.xy));
+}
+
+fn light_contribution(light: PointLight2d, pos: vec2<f32>, noise: f32) -> LightingOutput {
+    let dist = distance(light.center, pos);
+
+    if dist >= light.radius {
+        return empty_output();
+    }
+
+#ifdef DEBUG_STATS
+    raymarch_steps = 0.0;
+#endif
+
+    var visibility: f32;
+    if bool(light.cast_shadows) {
+        visibility = raymarch(light, pos, noise);
+    } else {
+        visibility = distance_factor(light, dist);
+    }
+
+    let transmittance = exp(-fog_optical_depth(light.center, pos));
+    let radiance = vec4(light.color.rgb, 1.0) * attenuation(light, dist) * visibility * transmittance;
+
+    // Scattering reuses the visibility of the surface lighting, so occluders carve shafts through
+    // the glow
+    let scattering = light.scattering * settings.scattering + vec4(fog_scattering(pos), 0.0);
+
+    var out = empty_output();
+    out.lighting = radiance;
+    out.scattering = radiance * scattering;
+#ifdef DEBUG_STATS
+    out.stats = vec4(raymarch_steps, 1.0, 0.0, 0.0);
+#endif
+    return out;
+}
+
+fn empty_output() -> LightingOutput {
+    var out: LightingOutput;
+    out.lighting = vec4(0.0);
+    out.scattering = vec4(0.0);
+#ifdef DEBUG_STATS
+    out.stats = vec4(0.0);
+#endif
+    return out;
+}
+
+fn fog_volume_count() -> u32 {
+#if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
+    return settings.fog_volume_count;
+#else
+    return min(settings.fog_volume_count, MAX_FOG_VOLUMES);
+#endif
+}
+
+// Density of the fog at `pos`, modulated by a scrolling value noise with a mean of about 1.0
+fn fog_density(fog: FogVolume2d, pos: vec2<f32>) -> f32 {
+    if !bool(fog.noise) {
+        return fog.density;
+    }
+
+    let p = (pos - fog.noise_scroll * settings.time) / FOG_NOISE_SCALE;
+    let noise = value_noise(p) * 0.65 + value_noise(p * 2.0 + 17.0) * 0.35;
+
+    return fog.density * noise * 2.0;
+}
+
+fn value_noise(p: vec2<f32>) -> f32 {
+    let cell = floor(p);
+    let f = fract(p);
+    let u = f * f * (3.0 - 2.0 * f);
+
+    return mix(
+        mix(hash(cell), hash(cell + vec2(1.0, 0.0)), u.x),
+        mix(hash(cell + vec2(0.0, 1.0)), hash(cell + vec2(1.0, 1.0)), u.x),
+        u.y,
+    );
+}
+
+fn hash(p: vec2<f32>) -> f32 {
+    return fract(sin(dot(p, vec2(127.1, 311.7))) * 43758.5453);
+}
+
+// Interval of the segment from `start` to `end` inside the volume, as fractions of the segment.
+// Empty when x >= y.
+fn fog_span(fog: FogVolume2d, start: vec2<f32>, end: vec2<f32>) -> vec2<f32> {
+    let delta = end - start;
+    let rel = start - fog.center;
+
+    if bool(fog.circle) {
+        let a = dot(delta, delta);
+        let b = dot(rel, delta);
+        let c = dot(rel, rel) - square(fog.half_size.x);
+        let discriminant = b * b - a * c;
+
+        if a <= 0.0 || discriminant <= 0.0 {
+            return vec2(1.0, 0.0);
+        }
+
+        let root = sqrt(discriminant);
+        return clamp(vec2(-b - root, -b + root) / a, vec2(0.0), vec2(1.0));
+    }
+
+    // Slab intersection, axis aligned segments get a tiny direction to avoid dividing by zero
+    let direction = select(delta, vec2(1e-6), abs(delta) < vec2(1e-6));
+    let t0 = (-fog.half_size - rel) / direction;
+    let t1 = (fog.half_size - rel) / direction;
+    let near = min(t0, t1);
+    let far = max(t0, t1);
+
+    return clamp(vec2(max(near.x, near.y), min(far.x, far.y)), vec2(0.0), vec2(1.0));
+}
+
+// Fog the light goes through from `start` to `end`, in extinction units. The density of noisy
+// volumes is sampled once in the middle of the crossed span.
+fn fog_optical_depth(start: vec2<f32>, end: vec2<f32>) -> f32 {
+    let segment_length = distance(start, end);
+    var depth = 0.0;
+
+    for (var i = 0u; i < fog_volume_count(); i++) {
+        let fog = fog_volumes[i];
+        let span = fog_span(fog, start, end);
 
-        if dist < light.radius {
-            lighting_color += vec4(light.color.rgb, 1.0) *
-                attenuation(light, dist) *
-                raymarch(light, pos);
+        if span.y > span.x {
+            let middle = mix(start, end, (span.x + span.y) * 0.5);
+            depth += fog_density(fog, middle) * (span.y - span.x) * segment_length;
         }
     }
 
-    return lighting_color;
+    return depth;
+}
+
+// Share of the light reaching `pos` scattered towards the camera by the volumes containing it
+fn fog_scattering(pos: vec2<f32>) -> vec3<f32> {
+    var scattering = vec3(0.0);
+
+    for (var i = 0u; i < fog_volume_count(); i++) {
+        let fog = fog_volumes[i];
+        let rel = abs(pos - fog.center);
+
+        var inside: bool;
+        if bool(fog.circle) {
+            inside = length(rel) <= fog.half_size.x;
+        } else {
+            inside = all(rel <= fog.half_size);
+        }
+
+        if inside {
+            let opacity = 1.0 - exp(-fog_density(fog, pos) * FOG_THICKNESS);
+            scattering += fog.color.rgb * opacity;
+        }
+    }
+
+    return scattering;
 }
 
 fn get_distance(pos: vec2<f32>) -> f32 {
@@ -73,9 +292,47 @@ fn attenuation(light: PointLight2d, dist: f32) -> f32 {
     return light.intensity * square(1 - s2) / (1 + light.falloff * s2);
 }
 
+fn distance_factor(light: PointLight2d, dist: f32) -> f32 {
+    // 1.0 next to the light and 0.0 at light.radius away
+    let fade_ratio = 1.0 - clamp(dist / light.radius, 0.0, 1.0);
+    // fade off quadratically instead of linearly
+    return pow(fade_ratio, 2.0);
+}
+
+// Noise in [0, 1) of the screen space jitter sources, offset every frame so temporal accumulation
+// averages it out
+fn jitter_noise(frag_coord: vec2<f32>) -> f32 {
+    let frame = f32(settings.frame % 64u);
+
+    switch settings.raymarch.jitter_source {
+        // blue noise
+        case 1u: {
+            let coord = vec2<u32>(frag_coord) % textureDimensions(blue_noise);
+            let noise = textureLoad(blue_noise, coord, 0).r;
+            return fract(noise + frame * GOLDEN_RATIO_CONJUGATE);
+        }
+        // interleaved gradient noise
+        // Attribution: https://blog.demofox.org/2022/01/01/interleaved-gradient-noise-a-different-kind-of-low-discrepancy-sequence
+        case 2u: {
+            let coord = frag_coord + 5.588238 * frame;
+            return fract(52.9829189 * fract(dot(coord, vec2(0.06711056, 0.00583715))));
+        }
+        // the hash source depends on each step, see `raymarch`
+        default: {
+            return 0.0;
+        }
+    }
+}
+
 // Implementation follows the demo of this article with some enhancements
 // https://www.rykap.com/2020/09/23/distance-fields
-fn raymarch(light: PointLight2d, ray_origin: vec2<f32>) -> f32 {
+//
+// Lights with a source radius estimate how much of the source is hidden instead: an occluder at
+// `dist` from the ray, `ray_progress` away from the receiver, hides half of the source when it
+// touches the ray and none of it when `dist / ray_progress` reaches the angular radius of the
+// source. The ray keeps going through occluders to find the inner half of the penumbra.
+// Attribution: https://iquilezles.org/articles/rmshadows
+fn raymarch(light: PointLight2d, ray_origin: vec2<f32>, noise: f32) -> f32 {
     let config = settings.raymarch;
     let max_steps = config.max_steps;
     let sharpness = config.sharpness;
@@ -83,31 +340,56 @@ fn raymarch(light: PointLight2d, ray_origin: vec2<f32>) -> f32 {
 
     let ray_direction = normalize(light.center - ray_origin);
     let stop_at = distance(ray_origin, light.center);
+    let has_source = light.source_radius > 0.0;
+    let source_angle = light.source_radius / stop_at;
 
     var ray_progress = 0.0;
     var light_contrib = 1.0;
 
     for (var i = 0u; i < max_steps; i++) {
+#ifdef DEBUG_STATS
+        raymarch_steps += 1.0;
+#endif
+
         // ray found target
         if (ray_progress > stop_at) {
-            // 1.0 next to the light and 0.0 at light.radius away
-            let fade_ratio = 1.0 - clamp(stop_at / light.radius, 0.0, 1.0);
-            // fade off quadratically instead of linearly
-            let distance_factor = pow(fade_ratio, 2.0);
+            if has_source {
+                light_contrib = smoothstep(-1.0, 1.0, light_contrib);
+            }
 
-            return light_contrib * distance_factor;
+            return light_contrib * distance_factor(light, stop_at);
         }
 
         let dist = get_distance(ray_origin + ray_progress * ray_direction);
+        var step = dist;
+
+        if has_source {
+            // -1.0 when the source is fully hidden, 1.0 when it's fully visible
+            light_contrib = min(light_contrib, dist / (ray_progress * source_angle));
+
+            if light_contrib <= -1.0 {
+                break;
+            }
 
-        // ray found occluder
-        if dist <= 0.0 {
-            break;
+            step = max(abs(dist), 1.0);
+        } else {
+            // ray found occluder
+            if dist <= 0.0 {
+                break;
+            }
+
+            light_contrib = min(light_contrib, dist / ray_progress * sharpness);
         }
 
-        light_contrib = min(light_contrib, dist / ray_progress * sharpness);
+        var step_noise: f32;
+        if config.jitter_source == 0u {
+            step_noise = fract(dist * 43758.5453);
+        } else {
+            // A different offset for each step
+            step_noise = fract(noise + f32(i) * GOLDEN_RATIO_CONJUGATE);
+        }
 
-        ray_progress += dist * (1.0 - jitter) + jitter * fract(dist * 43758.5453);
+        ray_progress += step * (1.0 - jitter) + jitter * step_noise;
     }
 
     return 0.0;
@@ -2,11 +2,71 @@
 
 @group(0) @binding(0) var view_texture: texture_2d<f32>;
 @group(0) @binding(1) var lighting_texture: texture_2d<f32>;
-@group(0) @binding(2) var texture_sampler: sampler;
+@group(0) @binding(2) var scattering_texture: texture_2d<f32>;
+@group(0) @binding(3) var texture_sampler: sampler;
+// Only read by the debug views
+@group(0) @binding(4) var sdf_texture: texture_2d<f32>;
+@group(0) @binding(5) var unblurred_lighting_texture: texture_2d<f32>;
+@group(0) @binding(6) var stats_texture: texture_2d<f32>;
+
+const TAU: f32 = 6.28318530718;
+// World units between the isolines of the SDF view
+const DEBUG_ISOLINE_SPACING: f32 = 16.0;
+// Raymarch steps shown as the hottest color of the heatmap
+const DEBUG_MAX_STEPS: f32 = 128.0;
+// Light count shown as the hottest color of the heatmap
+const DEBUG_MAX_LIGHTS: f32 = 16.0;
 
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let light_frag = textureSample(lighting_texture, texture_sampler, in.uv);
     let scene_frag = textureSample(view_texture, texture_sampler, in.uv);
-    return scene_frag * light_frag;
+    let scattering_frag = textureSample(scattering_texture, texture_sampler, in.uv);
+
+    // Scattered light glows in the air, independently of the surfaces underneath
+    let composite = scene_frag * light_frag + vec4(scattering_frag.rgb, 0.0);
+
+#ifdef DEBUG_SDF
+    let dist = textureSample(sdf_texture, texture_sampler, in.uv).r;
+    return vec4(sdf_color(dist, fwidth(dist)), 1.0);
+#else ifdef DEBUG_LIGHTING
+    return vec4(light_frag.rgb, 1.0);
+#else ifdef DEBUG_BLUR_COMPARISON
+    let unblurred = textureSample(unblurred_lighting_texture, texture_sampler, in.uv);
+    let divider = abs(in.uv.x - 0.5) < fwidth(in.uv.x);
+    if divider {
+        return vec4(1.0);
+    }
+    return vec4(select(light_frag.rgb, unblurred.rgb, in.uv.x < 0.5), 1.0);
+#else ifdef DEBUG_RAYMARCH_STEPS
+    let stats = textureSample(stats_texture, texture_sampler, in.uv);
+    return vec4(heatmap(stats.r / DEBUG_MAX_STEPS), 1.0);
+#else ifdef DEBUG_LIGHT_COUNT
+    let stats = textureSample(stats_texture, texture_sampler, in.uv);
+    return vec4(heatmap(stats.g / DEBUG_MAX_LIGHTS), 1.0);
+#else ifdef DEBUG_OCCLUDER_OUTLINES
+    let dist = textureSample(sdf_texture, texture_sampler, in.uv).r;
+    let outline = 1.0 - smoothstep(0.0, fwidth(dist) * 1.5, abs(dist));
+    return vec4(mix(composite.rgb, vec3(1.0, 0.0, 1.0), outline), composite.a);
+#else
+    return composite;
+#endif
+}
+
+// Orange outside of occluders and blue inside, darker near their edges, with isolines and a white
+// line on the edges
+// Attribution: https://iquilezles.org/articles/distfunctions2d
+fn sdf_color(dist: f32, pixel_size: f32) -> vec3<f32> {
+    var color = select(vec3(0.65, 0.85, 1.0), vec3(0.9, 0.6, 0.3), dist > 0.0);
+    color *= 1.0 - exp(-abs(dist) / (DEBUG_ISOLINE_SPACING * 4.0));
+    color *= 0.8 + 0.2 * cos(dist * TAU / DEBUG_ISOLINE_SPACING);
+
+    let edge = 1.0 - smoothstep(0.0, pixel_size * 1.5, abs(dist));
+    return mix(color, vec3(1.0), edge);
+}
+
+// Blue to green to red as `t` goes from 0.0 to 1.0
+fn heatmap(t: f32) -> vec3<f32> {
+    let x = saturate(t);
+    return saturate(vec3(x * 2.0 - 0.5, 1.0 - abs(x * 2.0 - 1.0) * 2.0 + 0.5, 1.5 - x * 2.0));
 }
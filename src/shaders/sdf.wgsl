@@ -1,36 +1,78 @@
 #import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
 #import bevy_lit::{
-    types::LightOccluder2d,
+    types::{Lighting2dSettings, LightOccluder2d},
     view_transformations::{frag_coord_to_ndc, position_ndc_to_world},
 }
 
+@group(0) @binding(1) var<uniform> settings: Lighting2dSettings;
+
 #if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
-    @group(0) @binding(1) var<storage> occluders: array<LightOccluder2d>;
+    @group(0) @binding(2) var<storage> occluders: array<LightOccluder2d>;
 #else
-    const MAX_OCCLUDERS: u32 = 82u;
+    const MAX_OCCLUDERS: u32 = #{MAX_OCCLUDERS}u;
 
-    @group(0) @binding(1) var<uniform> occluders: array<LightOccluder2d, MAX_OCCLUDERS>;
+    // One batch of occluders, unused slots are padded with occluders that are infinitely far
+    @group(0) @binding(2) var<uniform> occluders: array<LightOccluder2d, MAX_OCCLUDERS>;
 #endif
 
+// Largest finite half float, the distance reported when there are no occluders
+const MAX_DISTANCE: f32 = 65504.0;
+
 @fragment
 fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
     let pos = position_ndc_to_world(frag_coord_to_ndc(in.position)).xy;
 
 #if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
-    let occluder_count = arrayLength(&occluders);
+    let occluder_count = settings.occluder_count;
 #else
     let occluder_count = MAX_OCCLUDERS;
 #endif
 
-    // TODO: skiping first occluder that is a dummy. Find way to remove this.
-    var sdf = occluder_sd(pos, occluders[1]);
-    for (var i = 2u; i < occluder_count; i++) {
+    var sdf = MAX_DISTANCE;
+    for (var i = 0u; i < occluder_count; i++) {
         sdf = min(sdf, occluder_sd(pos, occluders[i]));
     }
 
     return vec4(sdf, 0.0, 0.0, 1.0);
 }
 
+struct MaterialOutput {
+    @location(0) albedo: vec4<f32>,
+    @location(1) emissive: vec4<f32>,
+}
+
+// Writes the material of the occluder covering each fragment, for global illumination
+@fragment
+fn material_fragment(in: FullscreenVertexOutput) -> MaterialOutput {
+    let pos = position_ndc_to_world(frag_coord_to_ndc(in.position)).xy;
+
+#if AVAILABLE_STORAGE_BUFFER_BINDINGS >= 6
+    let occluder_count = settings.occluder_count;
+#else
+    let occluder_count = MAX_OCCLUDERS;
+#endif
+
+    var sdf = 1.0;
+    var output: MaterialOutput;
+
+    for (var i = 0u; i < occluder_count; i++) {
+        let occluder = occluders[i];
+        let dist = occluder_sd(pos, occluder);
+
+        if dist <= sdf {
+            sdf = dist;
+            output.albedo = occluder.albedo;
+            output.emissive = occluder.emissive;
+        }
+    }
+
+    if sdf > 0.0 {
+        discard;
+    }
+
+    return output;
+}
+
 fn occluder_sd(p: vec2f, occluder: LightOccluder2d) -> f32 {
     let local_pos = occluder.center - p;
     let d = abs(local_pos) - occluder.half_size;
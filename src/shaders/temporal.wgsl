@@ -0,0 +1,50 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader::FullscreenVertexOutput
+#import bevy_lit::{
+    types::TemporalAccumulation,
+    view_transformations::{frag_coord_to_ndc, position_ndc_to_world, ndc_to_uv},
+}
+
+@group(0) @binding(1) var<uniform> temporal: TemporalAccumulation;
+@group(0) @binding(2) var lighting_texture: texture_2d<f32>;
+@group(0) @binding(3) var history_texture: texture_2d<f32>;
+@group(0) @binding(4) var texture_sampler: sampler;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let current = textureLoad(lighting_texture, vec2<i32>(in.position.xy), 0).rgb;
+
+    if bool(temporal.reset) {
+        return vec4(current, 1.0);
+    }
+
+    // Where this fragment was in the previous frame
+    let world_pos = position_ndc_to_world(frag_coord_to_ndc(in.position));
+    let previous_clip = temporal.previous_clip_from_world * vec4(world_pos, 1.0);
+    let previous_uv = ndc_to_uv(previous_clip.xy / previous_clip.w);
+
+    if any(previous_uv < vec2(0.0)) || any(previous_uv > vec2(1.0)) {
+        return vec4(current, 1.0);
+    }
+
+    // Clamping the history to the neighborhood of the current fragment avoids ghosting
+    var neighborhood_min = current;
+    var neighborhood_max = current;
+    let size = vec2<i32>(textureDimensions(lighting_texture)) - 1;
+
+    for (var x = -1; x <= 1; x++) {
+        for (var y = -1; y <= 1; y++) {
+            let coord = clamp(vec2<i32>(in.position.xy) + vec2(x, y), vec2(0), size);
+            let neighbor = textureLoad(lighting_texture, coord, 0).rgb;
+            neighborhood_min = min(neighborhood_min, neighbor);
+            neighborhood_max = max(neighborhood_max, neighbor);
+        }
+    }
+
+    let history = clamp(
+        textureSampleLevel(history_texture, texture_sampler, previous_uv, 0.0).rgb,
+        neighborhood_min,
+        neighborhood_max,
+    );
+
+    return vec4(mix(history, current, temporal.blend_factor), 1.0);
+}
@@ -4,19 +4,41 @@ struct RaymarchSettings {
     max_steps: u32,
     jitter: f32,
     sharpness: f32,
+    // 0: hash, 1: blue noise, 2: interleaved gradient noise
+    jitter_source: u32,
 }
 
 struct Lighting2dSettings {
     // blur circle of confusion diameter
     coc: f32,
     fixed_resolution: u32,
+    point_light_count: u32,
+    occluder_count: u32,
     ambient_light: vec4<f32>,
     raymarch: RaymarchSettings,
+    frame: u32,
+    // scattering tint multiplied by its intensity
+    scattering: vec4<f32>,
+    fog_volume_count: u32,
+    // seconds since startup
+    time: f32,
+    ambient_occlusion_radius: f32,
+    ambient_occlusion_intensity: f32,
+    // 0 when ambient occlusion is disabled
+    ambient_occlusion_samples: u32,
+}
+
+struct TemporalAccumulation {
+    previous_clip_from_world: mat4x4<f32>,
+    blend_factor: f32,
+    reset: u32,
 }
 
 struct LightOccluder2d {
     center: vec2<f32>,
     half_size: vec2<f32>,
+    albedo: vec4<f32>,
+    emissive: vec4<f32>,
 }
 
 struct PointLight2d {
@@ -25,4 +47,18 @@ struct PointLight2d {
     falloff: f32,
     intensity: f32,
     radius: f32,
+    cast_shadows: u32,
+    source_radius: f32,
+    scattering: f32,
+}
+
+struct FogVolume2d {
+    center: vec2<f32>,
+    // half size of a rect, or the radius of a circle in both components
+    half_size: vec2<f32>,
+    noise_scroll: vec2<f32>,
+    color: vec4<f32>,
+    density: f32,
+    circle: u32,
+    noise: u32,
 }
@@ -0,0 +1,456 @@
+use bevy::prelude::*;
+
+use crate::prelude::LightOccluder2d;
+
+// Occluders per BVH leaf
+const MAX_LEAF_SIZE: usize = 4;
+
+/// A bounding volume hierarchy of the visible [`LightOccluder2d`]s, for spatial queries on the
+/// CPU like line of sight checks, reusing the lighting geometry without a physics engine.
+///
+/// It's rebuilt in [`PostUpdate`] whenever an occluder is added, removed, moved, resized or
+/// hidden, so queries see the occluders of the previous frame until then.
+#[derive(Resource, Default)]
+pub struct OccluderBvh2d {
+    occluders: Vec<(Entity, Rect)>,
+    nodes: Vec<BvhNode>,
+}
+
+/// A hit of [`OccluderBvh2d::ray_cast`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OccluderRayHit2d {
+    /// The occluder hit.
+    pub entity: Entity,
+    /// Where the ray hit the occluder.
+    pub point: Vec2,
+    /// Normal of the occluder edge that was hit, opposite the ray if it started inside.
+    pub normal: Vec2,
+    /// Distance along the ray to `point`.
+    pub distance: f32,
+}
+
+// Leaves own `count` occluders from `first`. Branches have their left child right after them and
+// their right child at `first`
+#[derive(Clone, Copy)]
+struct BvhNode {
+    bounds: Rect,
+    first: usize,
+    count: usize,
+}
+
+impl OccluderBvh2d {
+    /// Builds the hierarchy of `(entity, AABB)` occluders.
+    pub fn new(occluders: impl IntoIterator<Item = (Entity, Rect)>) -> Self {
+        let mut bvh = Self {
+            occluders: occluders.into_iter().collect(),
+            nodes: Vec::new(),
+        };
+
+        if !bvh.occluders.is_empty() {
+            bvh.build(0, bvh.occluders.len());
+        }
+
+        bvh
+    }
+
+    /// Number of occluders in the hierarchy.
+    pub fn len(&self) -> usize {
+        self.occluders.len()
+    }
+
+    /// Whether there are no occluders in the hierarchy.
+    pub fn is_empty(&self) -> bool {
+        self.occluders.is_empty()
+    }
+
+    /// The first occluder hit by a ray from `origin` towards `direction`, within `max_distance`.
+    /// A ray starting inside an occluder hits it at `origin`.
+    pub fn ray_cast(
+        &self,
+        origin: Vec2,
+        direction: Vec2,
+        max_distance: f32,
+    ) -> Option<OccluderRayHit2d> {
+        let direction = direction.try_normalize()?;
+        let inverse_direction = direction.recip();
+        let mut closest: Option<OccluderRayHit2d> = None;
+
+        self.traverse(
+            &mut closest,
+            |closest, bounds| {
+                let max_distance = closest.map_or(max_distance, |hit| hit.distance);
+                ray_rect(origin, inverse_direction, bounds)
+                    .is_some_and(|(near, _)| near <= max_distance)
+            },
+            |closest, entity, rect| {
+                let Some((near, axis)) = ray_rect(origin, inverse_direction, rect) else {
+                    return;
+                };
+
+                if near > closest.map_or(max_distance, |hit| hit.distance) {
+                    return;
+                }
+
+                let normal = match axis {
+                    _ if near == 0.0 => -direction,
+                    0 => Vec2::new(-direction.x.signum(), 0.0),
+                    _ => Vec2::new(0.0, -direction.y.signum()),
+                };
+
+                *closest = Some(OccluderRayHit2d {
+                    entity,
+                    point: origin + direction * near,
+                    normal,
+                    distance: near,
+                });
+            },
+        );
+
+        closest
+    }
+
+    /// Signed distance from `point` to the closest occluder, negative inside occluders, along with
+    /// that occluder. `None` if there are no occluders.
+    pub fn distance(&self, point: Vec2) -> Option<(Entity, f32)> {
+        let mut closest: Option<(Entity, f32)> = None;
+
+        self.traverse(
+            &mut closest,
+            |closest, bounds| closest.map_or(true, |(_, dist)| rect_distance(bounds, point) < dist),
+            |closest, entity, rect| {
+                let dist = rect_distance(rect, point);
+                if closest.map_or(true, |(_, closest)| dist < closest) {
+                    *closest = Some((entity, dist));
+                }
+            },
+        );
+
+        closest
+    }
+
+    /// Occluders containing `point`.
+    pub fn overlaps(&self, point: Vec2) -> Vec<Entity> {
+        let mut overlaps = Vec::new();
+
+        self.traverse(
+            &mut overlaps,
+            |_, bounds| bounds.contains(point),
+            |overlaps, entity, rect| {
+                if rect.contains(point) {
+                    overlaps.push(entity);
+                }
+            },
+        );
+
+        overlaps
+    }
+
+    // Visits the occluders of the nodes accepted by `visit_node`, updating `state`
+    fn traverse<S>(
+        &self,
+        state: &mut S,
+        visit_node: impl Fn(&S, Rect) -> bool,
+        visit_occluder: impl Fn(&mut S, Entity, Rect),
+    ) {
+        let mut stack = Vec::new();
+
+        if !self.nodes.is_empty() {
+            stack.push(0);
+        }
+
+        while let Some(index) = stack.pop() {
+            let node = self.nodes[index];
+
+            if !visit_node(state, node.bounds) {
+                continue;
+            }
+
+            if node.count > 0 {
+                for &(entity, rect) in &self.occluders[node.first..node.first + node.count] {
+                    visit_occluder(state, entity, rect);
+                }
+            } else {
+                stack.push(node.first);
+                stack.push(index + 1);
+            }
+        }
+    }
+
+    // Splits the occluders at the median of the longest axis of their centers
+    fn build(&mut self, first: usize, count: usize) -> usize {
+        let occluders = &mut self.occluders[first..first + count];
+        let bounds = occluders
+            .iter()
+            .fold(occluders[0].1, |bounds, (_, rect)| bounds.union(*rect));
+        let index = self.nodes.len();
+
+        self.nodes.push(BvhNode {
+            bounds,
+            first,
+            count,
+        });
+
+        if count <= MAX_LEAF_SIZE {
+            return index;
+        }
+
+        let centers = occluders.iter().fold(Rect::EMPTY, |centers, (_, rect)| {
+            centers.union_point(rect.center())
+        });
+        let axis = if centers.width() >= centers.height() {
+            0
+        } else {
+            1
+        };
+        let half = count / 2;
+
+        occluders.select_nth_unstable_by(half, |(_, a), (_, b)| {
+            a.center()[axis].total_cmp(&b.center()[axis])
+        });
+
+        self.build(first, half);
+        let right = self.build(first + half, count - half);
+
+        self.nodes[index].first = right;
+        self.nodes[index].count = 0;
+
+        index
+    }
+}
+
+/// Distance to where the ray enters `rect`, 0.0 if it starts inside, and the axis of the entered
+/// edge. Attribution: https://tavianator.com/2011/ray_box.html
+fn ray_rect(origin: Vec2, inverse_direction: Vec2, rect: Rect) -> Option<(f32, usize)> {
+    let t1 = (rect.min - origin) * inverse_direction;
+    let t2 = (rect.max - origin) * inverse_direction;
+    // Rays parallel to an edge and aligned with it give NaNs, ignored by min and max
+    let near = t1.min(t2);
+    let far = t1.max(t2);
+
+    let enter = near.max_element();
+    let exit = far.min_element();
+
+    if exit < enter.max(0.0) {
+        return None;
+    }
+
+    let axis = if near.x > near.y { 0 } else { 1 };
+    Some((enter.max(0.0), axis))
+}
+
+// Same as `sdf.wgsl`
+fn rect_distance(rect: Rect, point: Vec2) -> f32 {
+    let d = (rect.center() - point).abs() - rect.half_size();
+    d.max(Vec2::ZERO).length() + d.x.max(d.y).min(0.0)
+}
+
+pub(crate) fn update_occluder_bvh(
+    mut bvh: ResMut<OccluderBvh2d>,
+    light_occluders_query: Query<(
+        Entity,
+        &LightOccluder2d,
+        &GlobalTransform,
+        &InheritedVisibility,
+    )>,
+    changed_query: Query<
+        (),
+        (
+            With<LightOccluder2d>,
+            Or<(
+                Changed<LightOccluder2d>,
+                Changed<GlobalTransform>,
+                Changed<InheritedVisibility>,
+            )>,
+        ),
+    >,
+    mut removed: RemovedComponents<LightOccluder2d>,
+) {
+    // Read even when something changed, or the removals would trigger another rebuild next frame
+    let removed = removed.read().count() > 0;
+
+    if changed_query.is_empty() && !removed {
+        return;
+    }
+
+    let occluders = light_occluders_query
+        .iter()
+        .filter(|(_, _, _, visibility)| visibility.get())
+        .map(|(entity, light_occluder, transform, _)| {
+            let center = transform.translation().xy();
+            (
+                entity,
+                Rect::from_center_half_size(center, light_occluder.half_size),
+            )
+        });
+
+    *bvh = OccluderBvh2d::new(occluders);
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::ecs::system::RunSystemOnce;
+
+    use super::*;
+
+    fn grid() -> OccluderBvh2d {
+        // 10x10 boxes of half size 5, 20 units apart
+        OccluderBvh2d::new((0..100).map(|i| {
+            let center = Vec2::new((i % 10) as f32, (i / 10) as f32) * 20.0;
+            (
+                Entity::from_raw(i),
+                Rect::from_center_half_size(center, Vec2::splat(5.0)),
+            )
+        }))
+    }
+
+    #[test]
+    fn ray_cast_hits_the_closest_occluder() {
+        let bvh = grid();
+
+        let hit = bvh
+            .ray_cast(Vec2::new(-20.0, 40.0), Vec2::X, 1000.0)
+            .unwrap();
+
+        assert_eq!(hit.entity, Entity::from_raw(20));
+        assert_eq!(hit.point, Vec2::new(-5.0, 40.0));
+        assert_eq!(hit.normal, Vec2::NEG_X);
+        assert_eq!(hit.distance, 15.0);
+
+        let hit = bvh
+            .ray_cast(Vec2::new(40.0, 300.0), Vec2::NEG_Y, 1000.0)
+            .unwrap();
+
+        assert_eq!(hit.entity, Entity::from_raw(92));
+        assert_eq!(hit.normal, Vec2::Y);
+    }
+
+    #[test]
+    fn ray_cast_misses() {
+        let bvh = grid();
+
+        // Between two rows, and too short
+        assert!(bvh
+            .ray_cast(Vec2::new(-20.0, 10.0), Vec2::X, 1000.0)
+            .is_none());
+        assert!(bvh.ray_cast(Vec2::new(-20.0, 0.0), Vec2::X, 10.0).is_none());
+        assert!(bvh
+            .ray_cast(Vec2::new(-20.0, 0.0), Vec2::NEG_X, 1000.0)
+            .is_none());
+        assert!(bvh.ray_cast(Vec2::ZERO, Vec2::ZERO, 1000.0).is_none());
+    }
+
+    #[test]
+    fn ray_cast_from_inside() {
+        let hit = grid().ray_cast(Vec2::ZERO, Vec2::Y, 1000.0).unwrap();
+
+        assert_eq!(hit.entity, Entity::from_raw(0));
+        assert_eq!(hit.distance, 0.0);
+        assert_eq!(hit.normal, Vec2::NEG_Y);
+    }
+
+    #[test]
+    fn distance_matches_brute_force() {
+        let bvh = grid();
+
+        for point in [
+            Vec2::new(-30.0, -30.0),
+            Vec2::new(10.0, 10.0),
+            Vec2::new(41.0, 62.0),
+            Vec2::new(500.0, 100.0),
+        ] {
+            let expected = bvh
+                .occluders
+                .iter()
+                .map(|(_, rect)| rect_distance(*rect, point))
+                .fold(f32::MAX, f32::min);
+
+            assert_eq!(bvh.distance(point).unwrap().1, expected);
+        }
+
+        assert_eq!(
+            bvh.distance(Vec2::new(41.0, 62.0)),
+            Some((Entity::from_raw(32), -3.0))
+        );
+        assert!(OccluderBvh2d::default().distance(Vec2::ZERO).is_none());
+    }
+
+    #[test]
+    fn overlaps() {
+        let bvh = OccluderBvh2d::new([
+            (Entity::from_raw(0), Rect::new(0.0, 0.0, 10.0, 10.0)),
+            (Entity::from_raw(1), Rect::new(5.0, 5.0, 15.0, 15.0)),
+        ]);
+
+        assert_eq!(bvh.overlaps(Vec2::splat(2.0)), [Entity::from_raw(0)]);
+        assert_eq!(bvh.overlaps(Vec2::splat(7.0)).len(), 2);
+        assert!(bvh.overlaps(Vec2::splat(20.0)).is_empty());
+    }
+
+    #[test]
+    fn rebuilt_on_changes() {
+        let mut world = World::new();
+        world.init_resource::<OccluderBvh2d>();
+
+        let occluder = world
+            .spawn((
+                LightOccluder2d::new(Vec2::splat(10.0)),
+                GlobalTransform::default(),
+                InheritedVisibility::VISIBLE,
+            ))
+            .id();
+        world.spawn((
+            LightOccluder2d::new(Vec2::splat(10.0)),
+            GlobalTransform::default(),
+            InheritedVisibility::HIDDEN,
+        ));
+
+        world.run_system_once(update_occluder_bvh);
+        assert_eq!(world.resource::<OccluderBvh2d>().len(), 1);
+
+        *world.get_mut::<GlobalTransform>(occluder).unwrap() =
+            GlobalTransform::from_xyz(100.0, 0.0, 0.0);
+        world.run_system_once(update_occluder_bvh);
+        assert_eq!(
+            world
+                .resource::<OccluderBvh2d>()
+                .overlaps(Vec2::new(100.0, 0.0)),
+            [occluder]
+        );
+
+        world.despawn(occluder);
+        world.run_system_once(update_occluder_bvh);
+        assert!(world.resource::<OccluderBvh2d>().is_empty());
+    }
+
+    #[test]
+    fn not_rebuilt_without_changes() {
+        let mut world = World::new();
+        world.init_resource::<OccluderBvh2d>();
+        let system = world.register_system(update_occluder_bvh);
+
+        let occluders = [Vec2::ZERO, Vec2::new(100.0, 0.0)].map(|position| {
+            world
+                .spawn((
+                    LightOccluder2d::new(Vec2::splat(10.0)),
+                    GlobalTransform::from_translation(position.extend(0.0)),
+                    InheritedVisibility::VISIBLE,
+                ))
+                .id()
+        });
+        world.run_system(system).unwrap();
+        assert_eq!(world.resource::<OccluderBvh2d>().len(), 2);
+
+        // Changed and removed in the same frame
+        *world.get_mut::<GlobalTransform>(occluders[0]).unwrap() =
+            GlobalTransform::from_xyz(0.0, 100.0, 0.0);
+        world.despawn(occluders[1]);
+        world.run_system(system).unwrap();
+        assert_eq!(world.resource::<OccluderBvh2d>().len(), 1);
+
+        // Left as is by the following runs, which would otherwise rebuild it from the occluder
+        world.insert_resource(OccluderBvh2d::default());
+        world.run_system(system).unwrap();
+        world.run_system(system).unwrap();
+        assert!(world.resource::<OccluderBvh2d>().is_empty());
+    }
+}
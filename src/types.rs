@@ -2,15 +2,18 @@ use bevy::{
     math::Vec2,
     prelude::*,
     reflect::Reflect,
-    render::{
-        render_resource::ShaderType,
-        view::{InheritedVisibility, ViewVisibility, Visibility},
-    },
+    render::view::{InheritedVisibility, ViewVisibility, Visibility},
     transform::components::{GlobalTransform, Transform},
 };
 
 /// Represents ambient light in a 2D environment. This component belongs to a [`Camera2d`] entity.
-#[derive(Component, Clone, Reflect)]
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
 pub struct AmbientLight2d {
     /// The color of the ambient light.
     pub color: Color,
@@ -27,15 +30,40 @@ impl Default for AmbientLight2d {
     }
 }
 
+/// Source of the noise jittering the raymarch steps
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[reflect(Default)]
+pub enum JitterSource {
+    /// A hash of the distance to the closest occluder (default). The pattern is structured and
+    /// doesn't change over time
+    #[default]
+    Hash,
+    /// Screen space blue noise from an embedded texture, offset every frame
+    BlueNoise,
+    /// Interleaved gradient noise, animated every frame
+    InterleavedGradient,
+}
+
 /// Raymarch settings
-#[derive(Reflect, Clone, ShaderType)]
+#[derive(Reflect, Clone, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
 pub struct RaymarchSettings {
     /// The maximum steps the raymarch loop can take to return a result
     pub max_steps: u32,
     /// Random number from 0.0 to 1.0. Maximizes the number of raymarching steps, improving approximation
     pub jitter_contrib: f32,
-    /// How sharp should the shadow projections be
+    /// How sharp should the shadow projections be. Only affects lights without a
+    /// [`PointLight2d::source_radius`]
     pub sharpness: f32,
+    /// Noise used by the jitter. Sources animated every frame are best paired with
+    /// [`Lighting2dSettings::temporal_accumulation`]
+    pub jitter_source: JitterSource,
 }
 
 impl Default for RaymarchSettings {
@@ -44,20 +72,204 @@ impl Default for RaymarchSettings {
             max_steps: 32,
             jitter_contrib: 0.5,
             sharpness: 5.0,
+            jitter_source: JitterSource::Hash,
+        }
+    }
+}
+
+/// Limits the number of point lights a camera renders. When there are more candidates than the
+/// budget allows, lights are ranked by [`PointLight2d::priority`], then by how much of the screen
+/// they cover and finally by their distance to the camera.
+#[derive(Reflect, Clone, Default, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct LightBudget {
+    /// Maximum number of point lights rendered with shadows. If `None` (default), every light is
+    /// rendered
+    pub max_lights: Option<u32>,
+    /// Number of lights ranked right after the first `max_lights` that are still rendered, but
+    /// without shadows
+    pub unshadowed_lights: u32,
+    /// Duration in seconds of the fade in and out of lights entering or leaving the budget. No fade
+    /// is applied if 0.0 (default)
+    pub fade_duration: f32,
+}
+
+/// How point lights are accumulated into the lighting texture
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[reflect(Default)]
+pub enum LightingStrategy {
+    /// A single fullscreen pass evaluates every light for every pixel (default)
+    #[default]
+    Fullscreen,
+    /// Each light is drawn as a quad covering its radius, so the cost of a pixel scales with the
+    /// number of lights overlapping it instead of the total number of lights
+    LightVolumes,
+}
+
+/// Blends the lighting of each frame with the lighting of previous frames, reprojected with the
+/// camera movement. Raymarch jitter averages out over time, giving smooth shadows with few
+/// [`RaymarchSettings::max_steps`].
+#[derive(Reflect, Clone, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct TemporalAccumulation {
+    /// Weight of the current frame in the blend, from 0.0 to 1.0. Lower values remove more noise
+    /// but take longer to catch up with changes
+    pub blend_factor: f32,
+}
+
+impl Default for TemporalAccumulation {
+    fn default() -> Self {
+        Self { blend_factor: 0.1 }
+    }
+}
+
+/// Global illumination computed with radiance cascades. Light bounces once off occluders, tinted by
+/// their [`LightOccluder2d::albedo`], and occluders with an [`LightOccluder2d::emissive`] color
+/// light their surroundings.
+#[derive(Reflect, Clone, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct GlobalIllumination {
+    /// Number of cascades. Each one reaches 4 times further than the previous, so this sets how
+    /// far light travels
+    pub cascade_count: u32,
+    /// Distance in pixels between the probes of the first cascade. Higher values are faster but
+    /// blurrier
+    pub probe_spacing: u32,
+    /// Length in pixels of the rays of the first cascade
+    pub interval: f32,
+    /// The maximum steps each ray can take
+    pub max_steps: u32,
+    /// Multiplies the indirect light
+    pub intensity: f32,
+}
+
+impl Default for GlobalIllumination {
+    fn default() -> Self {
+        Self {
+            cascade_count: 5,
+            probe_spacing: 2,
+            interval: 4.0,
+            max_steps: 24,
+            intensity: 1.0,
+        }
+    }
+}
+
+/// Darkens the ambient light near occluders, so corners and contact areas don't look flat. Computed
+/// from the occluders SDF and applied to the ambient light only.
+#[derive(Reflect, Clone, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct AmbientOcclusion {
+    /// Distance in world units within which occluders darken the ambient light
+    pub radius: f32,
+    /// Strength of the occlusion, 1.0 fully darkens a point surrounded by occluders
+    pub intensity: f32,
+    /// Number of directions searched for occluders around each pixel
+    pub samples: u32,
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> Self {
+        Self {
+            radius: 32.0,
+            intensity: 1.0,
+            samples: 8,
+        }
+    }
+}
+
+/// Light scattered in the air by point lights with a [`PointLight2d::scattering`] amount. It's added
+/// on top of the lit scene instead of multiplying it.
+#[derive(Reflect, Clone, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct ScatteringSettings {
+    /// Tints the scattered light
+    pub color: Color,
+    /// Multiplies the scattered light
+    pub intensity: f32,
+}
+
+impl Default for ScatteringSettings {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 1.0,
         }
     }
 }
 
+/// Algorithm used to blur the lighting texture
+#[derive(Reflect, Clone, Copy, Default, PartialEq, Eq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[reflect(Default)]
+pub enum BlurMode {
+    /// Separable Gaussian blur, a horizontal pass followed by a vertical one (default)
+    #[default]
+    Gaussian,
+    /// Separable Gaussian blur that doesn't take samples across occluders, so light doesn't bleed
+    /// through walls. It can't use bilinear taps like [`BlurMode::Gaussian`], making it more costly
+    Bilateral,
+    /// Dual Kawase blur, a chain of downsamples followed by as many upsamples. It approximates a
+    /// Gaussian blur at a cost that barely grows with the blur size, so it's cheaper for large blurs
+    DualKawase,
+}
+
 /// Settings for 2D lighting. This component belongs to a [`Camera2d`] entity and is mandatory for
 /// lighting effects
-#[derive(Component, Clone, Reflect)]
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
 pub struct Lighting2dSettings {
     /// The blur coc (circle of confusion) dimension contributing to the softness of the shadows
     pub blur: f32,
     /// If true (default), the blur is constant, else it's calculated in relation to the viewport size
     pub fixed_resolution: bool,
+    /// How the blur is computed
+    pub blur_mode: BlurMode,
     /// Raymarch settings
     pub raymarch: RaymarchSettings,
+    /// Point light budget
+    pub light_budget: LightBudget,
+    /// How point lights are accumulated
+    pub strategy: LightingStrategy,
+    /// Temporal accumulation of the lighting. Disabled if `None` (default)
+    pub temporal_accumulation: Option<TemporalAccumulation>,
+    /// Global illumination. Disabled if `None` (default)
+    pub global_illumination: Option<GlobalIllumination>,
+    /// Light scattering settings
+    pub scattering: ScatteringSettings,
+    /// Ambient occlusion. Disabled if `None` (default)
+    pub ambient_occlusion: Option<AmbientOcclusion>,
 }
 
 impl Default for Lighting2dSettings {
@@ -65,13 +277,53 @@ impl Default for Lighting2dSettings {
         Self {
             blur: 0.0,
             fixed_resolution: true,
+            blur_mode: Default::default(),
             raymarch: Default::default(),
+            light_budget: Default::default(),
+            strategy: Default::default(),
+            temporal_accumulation: None,
+            global_illumination: None,
+            scattering: Default::default(),
+            ambient_occlusion: None,
         }
     }
 }
 
+/// Replaces the lit scene of a [`Camera2d`] entity with a view of an intermediate lighting buffer.
+#[derive(Component, Clone, Copy, Reflect, PartialEq, Eq, Hash, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[reflect(Component)]
+pub enum Lighting2dDebug {
+    /// The distance to the closest occluder, orange outside of occluders and blue inside, with
+    /// isolines every 16 world units
+    Sdf,
+    /// The lighting buffer the scene is multiplied with
+    Lighting,
+    /// The lighting before the blur on the left half of the screen, and after it on the right
+    BlurComparison,
+    /// Heatmap of the raymarch steps taken for all the lights, from blue to red at 128 steps
+    RaymarchSteps,
+    /// Heatmap of the number of lights reaching each pixel, from blue to red at 16 lights
+    LightCount,
+    /// The lit scene with the outlines of the occluders
+    OccluderOutlines,
+}
+
+impl Lighting2dDebug {
+    /// Whether the lighting pass records the raymarch steps and light count for this view
+    pub fn needs_stats(&self) -> bool {
+        matches!(self, Self::RaymarchSteps | Self::LightCount)
+    }
+}
+
 /// Represents a point light in a 2D environment.
-#[derive(Component, Clone, Reflect)]
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
 pub struct PointLight2d {
     /// The color of the point light.
     pub color: Color,
@@ -81,6 +333,15 @@ pub struct PointLight2d {
     pub radius: f32,
     /// The falloff rate of the point light.
     pub falloff: f32,
+    /// How much light scatters in the air within the light radius, making it glow with visible
+    /// shafts where occluders block it. Tuned for all lights with [`Lighting2dSettings::scattering`]
+    pub scattering: f32,
+    /// The radius of the light source itself. Larger sources cast softer shadows, with penumbrae
+    /// widening as the distance between occluder and lit surface grows. If 0.0 (default), the
+    /// softness comes from [`RaymarchSettings::sharpness`] instead.
+    pub source_radius: f32,
+    /// Lights with a higher priority are kept first when a camera's [`LightBudget`] is exceeded.
+    pub priority: i32,
 }
 
 impl Default for PointLight2d {
@@ -90,6 +351,9 @@ impl Default for PointLight2d {
             intensity: 1.0,
             radius: 64.0,
             falloff: 1.0,
+            scattering: 0.0,
+            source_radius: 0.0,
+            priority: 0,
         }
     }
 }
@@ -112,15 +376,38 @@ pub struct PointLight2dBundle {
 }
 
 /// Represents an occluder that blocks light in a 2D environment.
-#[derive(Component, Default, Clone, Reflect)]
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
 pub struct LightOccluder2d {
     /// Half the size of the occluder AABB rectangle.
     pub half_size: Vec2,
+    /// The color of the light bouncing off the occluder. Only used by [`GlobalIllumination`].
+    pub albedo: Color,
+    /// The light emitted by the occluder. Only used by [`GlobalIllumination`].
+    pub emissive: Color,
+}
+
+impl Default for LightOccluder2d {
+    fn default() -> Self {
+        Self {
+            half_size: Vec2::ZERO,
+            albedo: Color::WHITE,
+            emissive: Color::BLACK,
+        }
+    }
 }
 
 impl LightOccluder2d {
     pub fn new(half_size: Vec2) -> Self {
-        Self { half_size }
+        Self {
+            half_size,
+            ..default()
+        }
     }
 }
 
@@ -140,3 +427,77 @@ pub struct LightOccluder2dBundle {
     /// The view visibility component.
     pub view_visibility: ViewVisibility,
 }
+
+/// Shape of a [`FogVolume2d`], centered on its transform.
+#[derive(Clone, Copy, Reflect, PartialEq, Debug)]
+#[cfg_attr(feature = "serialize", derive(serde::Serialize, serde::Deserialize))]
+#[reflect(Default)]
+pub enum FogShape2d {
+    /// A rectangle with the given half size
+    Rect { half_size: Vec2 },
+    /// A circle with the given radius
+    Circle { radius: f32 },
+}
+
+impl Default for FogShape2d {
+    fn default() -> Self {
+        Self::Rect {
+            half_size: Vec2::ZERO,
+        }
+    }
+}
+
+/// A region of participating media, like a fogged swamp or a smoke cloud. Light passing through it
+/// is attenuated, dimming the lights behind it, and light reaching it scatters inside, making it
+/// glow when lit.
+///
+/// Without storage buffers (WebGL2), only the first
+/// [`UniformArrayLengths::fog_volumes`](crate::prelude::UniformArrayLengths::fog_volumes) volumes,
+/// 82 by default, are rendered and the others are ignored.
+#[derive(Component, Clone, Reflect, PartialEq, Debug)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Component, Default)]
+pub struct FogVolume2d {
+    /// The shape of the volume.
+    pub shape: FogShape2d,
+    /// How much light is absorbed and scattered per world unit. Light crossing `1.0 / density`
+    /// units of fog keeps about a third of its intensity.
+    pub density: f32,
+    /// The color of the light scattered inside the volume.
+    pub color: Color,
+    /// Modulates the density with a noise scrolling at this velocity, in world units per second.
+    /// Uniform density if `None` (default).
+    pub noise_scroll: Option<Vec2>,
+}
+
+impl Default for FogVolume2d {
+    fn default() -> Self {
+        Self {
+            shape: default(),
+            density: 0.01,
+            color: Color::WHITE,
+            noise_scroll: None,
+        }
+    }
+}
+
+/// A bundle of components representing a fog volume in a 2D environment.
+#[derive(Bundle, Default)]
+pub struct FogVolume2dBundle {
+    /// The fog volume component.
+    pub fog_volume: FogVolume2d,
+    /// The transform component.
+    pub transform: Transform,
+    /// The global transform component.
+    pub global_transform: GlobalTransform,
+    /// The visibility component.
+    pub visibility: Visibility,
+    /// The inherited visibility component.
+    pub inherited_visibility: InheritedVisibility,
+    /// The view visibility component.
+    pub view_visibility: ViewVisibility,
+}
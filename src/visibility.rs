@@ -0,0 +1,316 @@
+use std::f32::consts::{PI, TAU};
+
+use bevy::{
+    prelude::*,
+    render::{
+        mesh::{Indices, PrimitiveTopology},
+        render_asset::RenderAssetUsages,
+    },
+};
+
+use crate::pipeline::MAX_DISTANCE;
+
+// Rays cast on each side of an occluder corner, to see past it
+const CORNER_EPSILON: f32 = 1e-4;
+// Tolerance of the ray hits at the ends of occluder edges
+const EDGE_EPSILON: f32 = 1e-5;
+// Segments approximating a full turn of the radius arc
+const ARC_SEGMENTS: u32 = 64;
+
+/// Limits of a [`VisibilityPolygon2d`], like the vision cone of an enemy.
+#[derive(Clone, Copy, Debug, Reflect, PartialEq)]
+#[cfg_attr(
+    feature = "serialize",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(default)
+)]
+#[reflect(Default)]
+pub struct FieldOfView2d {
+    /// How far can be seen. If `None` (default), up to the occluders or 65504 units, the largest
+    /// distance the half float SDF holds.
+    pub radius: Option<f32>,
+    /// Direction looked at, the center of the field of view. Defaults to `Vec2::X`.
+    pub direction: Vec2,
+    /// Angle seen around `direction`, in radians. Defaults to `TAU`, seeing all around.
+    pub angle: f32,
+}
+
+impl Default for FieldOfView2d {
+    fn default() -> Self {
+        Self {
+            radius: None,
+            direction: Vec2::X,
+            angle: TAU,
+        }
+    }
+}
+
+/// The region visible from an `origin` point, bounded by the [`LightOccluder2d`] set and a
+/// [`FieldOfView2d`]. Computed with [`LightingQuery2d::visibility_polygon`].
+///
+/// [`LightOccluder2d`]: crate::prelude::LightOccluder2d
+/// [`LightingQuery2d::visibility_polygon`]: crate::prelude::LightingQuery2d::visibility_polygon
+#[derive(Clone, Debug, Default)]
+pub struct VisibilityPolygon2d {
+    /// The point seen from.
+    pub origin: Vec2,
+    /// World space vertices in counterclockwise order. The polygon is star-shaped around `origin`,
+    /// which is the first vertex if the field of view doesn't go all around. Empty if `origin` is
+    /// inside an occluder.
+    pub vertices: Vec<Vec2>,
+}
+
+impl VisibilityPolygon2d {
+    /// Builds a triangle fan mesh of the polygon, with world space positions, for rendering fog of
+    /// war or vision cones with an untransformed `Mesh2d`. The UVs span the polygon bounds.
+    pub fn mesh(&self) -> Mesh {
+        let mut positions = vec![self.origin.extend(0.0)];
+        positions.extend(self.vertices.iter().map(|vertex| vertex.extend(0.0)));
+
+        let (min, max) = positions.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), position| (min.min(*position), max.max(*position)),
+        );
+        let size = (max - min).xy().max(Vec2::splat(f32::EPSILON));
+
+        let uvs: Vec<[f32; 2]> = positions
+            .iter()
+            .map(|position| {
+                let uv = (position.xy() - min.xy()) / size;
+                [uv.x, 1.0 - uv.y]
+            })
+            .collect();
+
+        let count = self.vertices.len() as u32;
+        let indices = (0..count)
+            .flat_map(|i| [0, i + 1, (i + 1) % count + 1])
+            .collect();
+
+        Mesh::new(
+            PrimitiveTopology::TriangleList,
+            RenderAssetUsages::default(),
+        )
+        .with_inserted_attribute(
+            Mesh::ATTRIBUTE_NORMAL,
+            vec![[0.0, 0.0, 1.0]; positions.len()],
+        )
+        .with_inserted_attribute(Mesh::ATTRIBUTE_POSITION, positions)
+        .with_inserted_attribute(Mesh::ATTRIBUTE_UV_0, uvs)
+        .with_inserted_indices(Indices::U32(indices))
+    }
+
+    /// Whether `point` is inside the polygon.
+    pub fn contains(&self, point: Vec2) -> bool {
+        let mut inside = false;
+        let mut previous = match self.vertices.last() {
+            Some(vertex) => *vertex,
+            None => return false,
+        };
+
+        for &vertex in &self.vertices {
+            if (vertex.y > point.y) != (previous.y > point.y)
+                && point.x
+                    < (previous.x - vertex.x) * (point.y - vertex.y) / (previous.y - vertex.y)
+                        + vertex.x
+            {
+                inside = !inside;
+            }
+            previous = vertex;
+        }
+
+        inside
+    }
+}
+
+/// Visibility polygon from `origin` against `(center, half_size)` occluders, for occluders that
+/// aren't [`LightOccluder2d`](crate::prelude::LightOccluder2d) entities. Rays are cast towards
+/// every occluder corner and along the radius arc, and the polygon is empty if `origin` is inside
+/// an occluder.
+///
+/// Attribution: <https://www.redblobgames.com/articles/visibility>
+pub fn visibility_polygon(
+    origin: Vec2,
+    occluders: &[(Vec2, Vec2)],
+    field_of_view: &FieldOfView2d,
+) -> VisibilityPolygon2d {
+    let mut polygon = VisibilityPolygon2d {
+        origin,
+        vertices: Vec::new(),
+    };
+
+    if occluders
+        .iter()
+        .any(|&(center, half_size)| ((center - origin).abs() - half_size).max_element() < 0.0)
+    {
+        return polygon;
+    }
+
+    let radius = field_of_view.radius.unwrap_or(MAX_DISTANCE);
+    let half_angle = field_of_view.angle.clamp(0.0, TAU) / 2.0;
+    let full_turn = half_angle >= PI;
+    let facing = field_of_view.direction.to_angle();
+
+    // Angles relative to the facing direction, in (-PI, PI]
+    let relative = |angle: f32| {
+        let angle = (angle - facing).rem_euclid(TAU);
+        if angle > PI {
+            angle - TAU
+        } else {
+            angle
+        }
+    };
+
+    let segments: Vec<(Vec2, Vec2)> = occluders
+        .iter()
+        .flat_map(|&(center, half_size)| {
+            let corners = [
+                center + Vec2::new(-half_size.x, -half_size.y),
+                center + Vec2::new(half_size.x, -half_size.y),
+                center + Vec2::new(half_size.x, half_size.y),
+                center + Vec2::new(-half_size.x, half_size.y),
+            ];
+            (0..4).map(move |i| (corners[i], corners[(i + 1) % 4]))
+        })
+        .collect();
+
+    let arc_segments = ((ARC_SEGMENTS as f32 * half_angle / PI).ceil() as u32).max(1);
+    let mut angles: Vec<f32> = (0..=arc_segments)
+        .map(|i| -half_angle + 2.0 * half_angle * i as f32 / arc_segments as f32)
+        .collect();
+
+    for &(corner, _) in &segments {
+        let angle = relative((corner - origin).to_angle());
+        angles.extend([angle - CORNER_EPSILON, angle, angle + CORNER_EPSILON]);
+    }
+
+    angles.retain(|angle| angle.abs() <= half_angle);
+    angles.sort_by(f32::total_cmp);
+    angles.dedup();
+
+    if full_turn {
+        // -PI and PI are the same ray
+        angles.retain(|angle| *angle > -PI);
+    } else {
+        polygon.vertices.push(origin);
+    }
+
+    polygon.vertices.extend(angles.into_iter().map(|angle| {
+        let direction = Vec2::from_angle(facing + angle);
+        origin + direction * ray_cast(origin, direction, &segments, radius)
+    }));
+
+    polygon
+}
+
+/// Distance along the ray to the closest segment, or `max_distance`
+fn ray_cast(origin: Vec2, direction: Vec2, segments: &[(Vec2, Vec2)], max_distance: f32) -> f32 {
+    segments
+        .iter()
+        .filter_map(|&(start, end)| {
+            let edge = end - start;
+            let denominator = direction.perp_dot(edge);
+
+            if denominator.abs() < f32::EPSILON {
+                return None;
+            }
+
+            let to_start = start - origin;
+            let t = to_start.perp_dot(edge) / denominator;
+            let u = to_start.perp_dot(direction) / denominator;
+
+            // Rays towards a corner hit it despite rounding errors
+            (t >= 0.0 && (-EDGE_EPSILON..=1.0 + EDGE_EPSILON).contains(&u)).then_some(t)
+        })
+        .fold(max_distance, f32::min)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unobstructed_circle() {
+        let polygon = visibility_polygon(
+            Vec2::ZERO,
+            &[],
+            &FieldOfView2d {
+                radius: Some(100.0),
+                ..default()
+            },
+        );
+
+        assert_eq!(polygon.vertices.len(), ARC_SEGMENTS as usize);
+        assert!(polygon
+            .vertices
+            .iter()
+            .all(|vertex| (vertex.length() - 100.0).abs() < 1e-3));
+        assert!(polygon.contains(Vec2::new(90.0, 0.0)));
+        assert!(!polygon.contains(Vec2::new(110.0, 0.0)));
+    }
+
+    #[test]
+    fn occluder_hides_what_is_behind() {
+        let polygon = visibility_polygon(
+            Vec2::ZERO,
+            &[(Vec2::new(50.0, 0.0), Vec2::splat(10.0))],
+            &FieldOfView2d {
+                radius: Some(200.0),
+                ..default()
+            },
+        );
+
+        assert!(polygon.contains(Vec2::new(35.0, 0.0)));
+        assert!(!polygon.contains(Vec2::new(100.0, 0.0)));
+        assert!(polygon.contains(Vec2::new(100.0, 50.0)));
+        // The visible corners are exact
+        for corner in [Vec2::new(40.0, -10.0), Vec2::new(40.0, 10.0)] {
+            assert!(polygon
+                .vertices
+                .iter()
+                .any(|vertex| vertex.distance(corner) < 1e-3));
+        }
+    }
+
+    #[test]
+    fn cone() {
+        let polygon = visibility_polygon(
+            Vec2::ZERO,
+            &[],
+            &FieldOfView2d {
+                radius: Some(100.0),
+                direction: Vec2::Y,
+                angle: PI / 2.0,
+            },
+        );
+
+        assert_eq!(polygon.vertices[0], Vec2::ZERO);
+        assert!(polygon.contains(Vec2::new(0.0, 50.0)));
+        assert!(!polygon.contains(Vec2::new(50.0, 10.0)));
+        assert!(!polygon.contains(Vec2::new(0.0, -50.0)));
+    }
+
+    #[test]
+    fn inside_occluder() {
+        let polygon =
+            visibility_polygon(Vec2::ZERO, &[(Vec2::ZERO, Vec2::splat(10.0))], &default());
+
+        assert!(polygon.vertices.is_empty());
+        assert_eq!(polygon.mesh().count_vertices(), 1);
+    }
+
+    #[test]
+    fn mesh_is_a_triangle_fan() {
+        let polygon = visibility_polygon(
+            Vec2::ZERO,
+            &[],
+            &FieldOfView2d {
+                radius: Some(100.0),
+                ..default()
+            },
+        );
+        let mesh = polygon.mesh();
+
+        assert_eq!(mesh.count_vertices(), ARC_SEGMENTS as usize + 1);
+        assert_eq!(mesh.indices().unwrap().len(), ARC_SEGMENTS as usize * 3);
+    }
+}
@@ -0,0 +1,65 @@
+use bevy::prelude::*;
+use bevy_lit::prelude::*;
+
+// Fixtures setting most fields away from their defaults, shared by the round-trip tests
+
+pub fn settings() -> Lighting2dSettings {
+    Lighting2dSettings {
+        blur: 4.0,
+        blur_mode: BlurMode::DualKawase,
+        strategy: LightingStrategy::LightVolumes,
+        raymarch: RaymarchSettings {
+            max_steps: 48,
+            jitter_contrib: 0.25,
+            sharpness: 2.0,
+            jitter_source: JitterSource::BlueNoise,
+        },
+        light_budget: LightBudget {
+            max_lights: Some(8),
+            unshadowed_lights: 4,
+            fade_duration: 0.5,
+        },
+        temporal_accumulation: Some(TemporalAccumulation::default()),
+        global_illumination: Some(GlobalIllumination::default()),
+        ambient_occlusion: Some(AmbientOcclusion {
+            radius: 16.0,
+            ..default()
+        }),
+        ..default()
+    }
+}
+
+pub fn ambient_light() -> AmbientLight2d {
+    AmbientLight2d {
+        color: Color::srgb(0.2, 0.3, 0.8),
+        brightness: 0.4,
+    }
+}
+
+pub fn point_light() -> PointLight2d {
+    PointLight2d {
+        color: Color::srgb(1.0, 0.5, 0.0),
+        intensity: 3.0,
+        radius: 200.0,
+        falloff: 2.0,
+        scattering: 0.5,
+        source_radius: 8.0,
+        priority: -1,
+    }
+}
+
+pub fn light_occluder() -> LightOccluder2d {
+    LightOccluder2d {
+        half_size: Vec2::new(16.0, 32.0),
+        albedo: Color::srgb(0.5, 0.1, 0.1),
+        emissive: Color::linear_rgb(2.0, 0.0, 0.0),
+    }
+}
+
+pub fn fog_volume() -> FogVolume2d {
+    FogVolume2d {
+        shape: FogShape2d::Circle { radius: 40.0 },
+        noise_scroll: Some(Vec2::X),
+        ..default()
+    }
+}
@@ -0,0 +1,132 @@
+use bevy::{asset::ron, ecs::entity::EntityHashMap, prelude::*, scene::serde::SceneDeserializer};
+use bevy_lit::prelude::*;
+
+mod common;
+
+fn app() -> App {
+    let mut app = App::new();
+
+    // The plugin only needs the shader assets outside of a render app
+    app.add_plugins(AssetPlugin::default())
+        .init_asset::<Shader>()
+        .add_plugins(Lighting2dPlugin::default());
+
+    app
+}
+
+fn serialize(world: &World) -> String {
+    let registry = world.resource::<AppTypeRegistry>().read();
+
+    DynamicScene::from_world(world)
+        .serialize(&registry)
+        .expect("lighting components should serialize")
+}
+
+fn deserialize(world: &World, serialized: &str) -> DynamicScene {
+    let registry = world.resource::<AppTypeRegistry>().read();
+
+    ron::Options::default()
+        .from_str_seed(
+            serialized,
+            SceneDeserializer {
+                type_registry: &registry,
+            },
+        )
+        .expect("lighting components should deserialize")
+}
+
+#[test]
+fn lighting_components_round_trip() {
+    let mut saved = app();
+    let world = saved.world_mut();
+
+    world.spawn((
+        common::settings(),
+        common::ambient_light(),
+        Lighting2dDebug::Sdf,
+    ));
+    world.spawn(common::point_light());
+    world.spawn(common::light_occluder());
+    world.spawn(common::fog_volume());
+    world.spawn((DayNightCycle2d::default(), Illuminated2d::default()));
+
+    let serialized = serialize(world);
+    let scene = deserialize(world, &serialized);
+
+    let mut loaded = app();
+    scene
+        .write_to_world(loaded.world_mut(), &mut EntityHashMap::default())
+        .expect("lighting components should be inserted");
+
+    // Loaded back identically, down to the last field
+    assert_eq!(serialize(loaded.world()), serialized);
+
+    let loaded = loaded.world_mut();
+    let (settings, ambient_light, debug) = loaded
+        .query::<(&Lighting2dSettings, &AmbientLight2d, &Lighting2dDebug)>()
+        .single(loaded);
+    assert_eq!(*settings, common::settings());
+    assert_eq!(*ambient_light, common::ambient_light());
+    assert_eq!(*debug, Lighting2dDebug::Sdf);
+
+    let point_light = loaded.query::<&PointLight2d>().single(loaded);
+    assert_eq!(*point_light, common::point_light());
+
+    let light_occluder = loaded.query::<&LightOccluder2d>().single(loaded);
+    assert_eq!(*light_occluder, common::light_occluder());
+
+    let fog_volume = loaded.query::<&FogVolume2d>().single(loaded);
+    assert_eq!(*fog_volume, common::fog_volume());
+}
+
+#[test]
+fn missing_fields_use_defaults() {
+    let app = app();
+    let world = app.world();
+
+    let scene = deserialize(
+        world,
+        r#"(
+            resources: {},
+            entities: {
+                // Index 0, generation 1
+                4294967296: (
+                    components: {
+                        "bevy_lit::types::PointLight2d": (
+                            radius: 100.0,
+                        ),
+                        "bevy_lit::types::Lighting2dSettings": (
+                            raymarch: (
+                                max_steps: 8,
+                            ),
+                        ),
+                    },
+                ),
+            },
+        )"#,
+    );
+
+    let mut loaded = World::new();
+    loaded.insert_resource(world.resource::<AppTypeRegistry>().clone());
+    scene
+        .write_to_world(&mut loaded, &mut EntityHashMap::default())
+        .unwrap();
+
+    let (point_light, settings) = loaded
+        .query::<(&PointLight2d, &Lighting2dSettings)>()
+        .single(&loaded);
+    assert_eq!(
+        *point_light,
+        PointLight2d {
+            radius: 100.0,
+            ..default()
+        }
+    );
+    assert_eq!(
+        settings.raymarch,
+        RaymarchSettings {
+            max_steps: 8,
+            ..default()
+        }
+    );
+}
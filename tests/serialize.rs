@@ -0,0 +1,82 @@
+#![cfg(feature = "serialize")]
+
+use bevy::{asset::ron, prelude::*, utils::HashMap};
+use bevy_lit::prelude::*;
+use serde::{de::DeserializeOwned, Serialize};
+
+mod common;
+
+// The value is re-serialized after the round trip too, checking that nothing is lost or reordered
+fn assert_round_trip<T>(value: T)
+where
+    T: Serialize + DeserializeOwned + PartialEq + std::fmt::Debug,
+{
+    let serialized = ron::to_string(&value).expect("lighting types should serialize");
+    let loaded: T = ron::from_str(&serialized).expect("lighting types should deserialize");
+
+    assert_eq!(loaded, value);
+    assert_eq!(ron::to_string(&loaded).unwrap(), serialized);
+}
+
+#[test]
+fn lighting_types_round_trip() {
+    assert_round_trip(common::settings());
+    assert_round_trip(common::ambient_light());
+    assert_round_trip(common::point_light());
+    assert_round_trip(common::light_occluder());
+    assert_round_trip(common::fog_volume());
+    assert_round_trip(Lighting2dDebug::Sdf);
+    assert_round_trip(DayNightCycle2d::default());
+    assert_round_trip(FieldOfView2d {
+        radius: Some(100.0),
+        ..default()
+    });
+    assert_round_trip(Illuminated2d {
+        light: Some(LinearRgba::RED),
+    });
+    assert_round_trip(LightingProfile2d {
+        settings: common::settings(),
+        ambient_light: common::ambient_light(),
+        point_lights: HashMap::from([("torch".to_string(), common::point_light())]),
+    });
+}
+
+#[test]
+fn day_night_gradient_keyframes_are_sorted() {
+    let gradient: DayNightGradient<f32> = ron::from_str("[(18.0, 0.2), (6.0, 1.0)]").unwrap();
+
+    assert_eq!(gradient.keyframes(), [(6.0, 1.0), (18.0, 0.2)]);
+    assert_eq!(ron::to_string(&gradient).unwrap(), "[(6.0,1.0),(18.0,0.2)]");
+}
+
+// Saved files depend on these names, changing them is a breaking change
+#[test]
+fn stable_field_layout() {
+    assert_eq!(
+        ron::to_string(&RaymarchSettings::default()).unwrap(),
+        "(max_steps:32,jitter_contrib:0.5,sharpness:5.0,jitter_source:Hash)"
+    );
+    assert_eq!(
+        ron::to_string(&PointLight2d::default()).unwrap(),
+        "(color:LinearRgba((red:1.0,green:1.0,blue:1.0,alpha:1.0)),intensity:1.0,radius:64.0,\
+         falloff:1.0,scattering:0.0,source_radius:0.0,priority:0)"
+    );
+    assert_eq!(
+        ron::to_string(&LightOccluder2d::new(Vec2::splat(8.0))).unwrap(),
+        "(half_size:(8.0,8.0),albedo:LinearRgba((red:1.0,green:1.0,blue:1.0,alpha:1.0)),\
+         emissive:LinearRgba((red:0.0,green:0.0,blue:0.0,alpha:1.0)))"
+    );
+}
+
+#[test]
+fn missing_fields_use_defaults() {
+    let fog_volume: FogVolume2d = ron::from_str("(density: 0.5)").unwrap();
+
+    assert_eq!(
+        fog_volume,
+        FogVolume2d {
+            density: 0.5,
+            ..default()
+        }
+    );
+}